@@ -13,6 +13,31 @@ interface Scan {
     firstBlockTimestamp: string;
     nextBlockNumber: number;
     nextBlockTimestamp: string;
+    labels?: Record<string, { name: string }>;
+}
+
+interface TxTrace {
+    traceIndex: number;
+    fromAddr: string;
+    toAddr: string;
+    value: string;
+}
+
+interface TxWithTraces {
+    txHash: string;
+    blockIndex: number;
+    traces: Array<TxTrace>;
+}
+
+interface BlockWithTxs {
+    blockNumber: number;
+    txs: Array<TxWithTraces>;
+}
+
+interface CoverageGap {
+    blockStart: number;
+    blockEnd: number;
+    status: string | null;
 }
 
 const Blocks = () => {
@@ -24,9 +49,11 @@ const Blocks = () => {
     const [blocks, setBlocks] = React.useState<Array<BlockFromApi>>([]);
     const [loading, setLoading] = React.useState(false);
     const [scans, setScans] = React.useState<Array<Scan>>([]);
+    const [gaps, setGaps] = React.useState<Array<CoverageGap>>([]);
+    const [selectedBlock, setSelectedBlock] = React.useState<BlockWithTxs | null>(null);
     const getScans = async () => {
         setLoading(true);
-        const response = await backendFetch("/api/scan/all", {
+        const response = await backendFetch("/api/scan/all?labels=true", {
             method: "Get",
         });
         const data = await response.json();
@@ -39,19 +66,52 @@ const Blocks = () => {
     const getBlocks = async () => {
         setBlocks([]);
         setLoading(true);
-        const response = await backendFetch(`/api/scan/${address}/blocks`, {
+        let allBlocks: Array<BlockFromApi> = [];
+        let cursor: string | null = null;
+        do {
+            const query: string = cursor ? `?limit=10000&cursor=${encodeURIComponent(cursor)}` : "?limit=10000";
+            const response = await backendFetch(`/api/scan/${address}/blocks${query}`, {
+                method: "Get",
+            });
+            const data = await response.json();
+            allBlocks = allBlocks.concat(data.blocks);
+            cursor = data.nextCursor;
+        } while (cursor);
+        setBlocks(allBlocks);
+        setLoading(false);
+    };
+    const getCoverage = async () => {
+        setGaps([]);
+        const response = await backendFetch(`/api/scan/${address}/coverage`, {
             method: "Get",
         });
-        const data = await response.json();
-        setBlocks(data);
-        setLoading(false);
+        if (response.ok) {
+            const data = await response.json();
+            setGaps(data.gaps);
+        }
+    };
+    const getBlockDetails = async (blockNumber: number) => {
+        const response = await backendFetch(`/api/scan/${address}/blocks/${blockNumber}`, {
+            method: "Get",
+        });
+        if (response.ok) {
+            setSelectedBlock(await response.json());
+        }
+    };
+    const fillGap = async (gap: CoverageGap) => {
+        await backendFetch(`/api/scan/${address}/coverage/fill`, {
+            method: "Post",
+            body: JSON.stringify({ blockStart: gap.blockStart, blockEnd: gap.blockEnd }),
+        });
     };
     useEffect(() => {
         getScans().then();
     }, []);
 
     useEffect(() => {
+        setSelectedBlock(null);
         getBlocks().then();
+        getCoverage().then();
     }, [address]);
 
     const toClass = (balance: string | bigint) => {
@@ -138,7 +198,7 @@ const Blocks = () => {
     }
     const renderBlock = (idx: number, block: BlockFromApi) => {
         return (
-            <tr key={block.blockNumber}>
+            <tr key={block.blockNumber} onClick={() => getBlockDetails(block.blockNumber)}>
                 <td>{idx}</td>
                 <td>{block.blockNumber}</td>
                 <td>{block.timestamp}</td>
@@ -181,7 +241,7 @@ const Blocks = () => {
                     {scans.map((scan, idx) => {
                         return (
                             <option key={idx} selected={address == scan.address} value={scan.address}>
-                                {scan.address}
+                                {scan.labels?.[scan.address]?.name ?? scan.address}
                             </option>
                         );
                     })}
@@ -266,6 +326,40 @@ const Blocks = () => {
                     </td>
                 </tr>
             </table>
+            {selectedBlock && (
+                <div>
+                    <h3>Transactions of block {selectedBlock.blockNumber}</h3>
+                    {selectedBlock.txs.length == 0 && <div>No transactions, balance changed by rewards only</div>}
+                    {selectedBlock.txs.map((tx) => {
+                        return (
+                            <div key={tx.txHash + tx.blockIndex}>
+                                <div>
+                                    {tx.txHash} (index {tx.blockIndex})
+                                </div>
+                                {tx.traces.map((trace) => {
+                                    return (
+                                        <div key={trace.traceIndex} style={{ paddingLeft: 20 }}>
+                                            {trace.fromAddr} {"->"} {trace.toAddr}: <DisplayEther balance={trace.value} />
+                                        </div>
+                                    );
+                                })}
+                            </div>
+                        );
+                    })}
+                </div>
+            )}
+            <div>
+                <h3>Uninspected ranges</h3>
+                {gaps.length == 0 && <div>All scanned blocks were inspected</div>}
+                {gaps.map((gap) => {
+                    return (
+                        <div key={gap.blockStart}>
+                            {gap.blockStart} - {gap.blockEnd - 1} ({gap.status ?? "not recorded"})
+                            <button onClick={() => fillGap(gap)}>Fill</button>
+                        </div>
+                    );
+                })}
+            </div>
             <div>
                 <h3>Checks</h3>
                 <div>Difference Between last and first block:</div>
@@ -14,6 +14,8 @@ export interface BlockFromApi {
     blockReward: string;
     amountIncoming: string;
     amountOutgoing: string;
+    traceMode: string;
+    unexplainedValue: string | null;
 }
 
 export interface BlocksSummary {
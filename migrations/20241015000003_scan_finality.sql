@@ -0,0 +1,2 @@
+ALTER TABLE scan ADD COLUMN finality_policy TEXT;
+ALTER TABLE scan ADD COLUMN finality_block_number INT;
@@ -0,0 +1,2 @@
+ALTER TABLE block ADD COLUMN trace_mode TEXT NOT NULL DEFAULT 'trace_transaction';
+ALTER TABLE block ADD COLUMN unexplained_value TEXT;
@@ -0,0 +1,15 @@
+CREATE TABLE failed_block
+(
+    address TEXT NOT NULL,
+    block_number INT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INT NOT NULL,
+    first_failed TEXT NOT NULL,
+    last_attempt TEXT NOT NULL,
+    next_retry TEXT NOT NULL,
+
+    CONSTRAINT failed_block_pk PRIMARY KEY (address, block_number),
+    CONSTRAINT failed_block_scan_fk FOREIGN KEY (address)
+        REFERENCES scan (address)
+        ON DELETE CASCADE
+) strict;
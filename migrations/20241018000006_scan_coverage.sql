@@ -0,0 +1,13 @@
+CREATE TABLE scan_coverage
+(
+    address TEXT NOT NULL,
+    block_start INT NOT NULL,
+    block_end INT NOT NULL,
+    status TEXT NOT NULL,
+    updated TEXT NOT NULL,
+
+    CONSTRAINT scan_coverage_pk PRIMARY KEY (address, block_start),
+    CONSTRAINT scan_coverage_scan_fk FOREIGN KEY (address)
+        REFERENCES scan (address)
+        ON DELETE CASCADE
+) strict;
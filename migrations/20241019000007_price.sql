@@ -0,0 +1,8 @@
+CREATE TABLE price
+(
+    currency TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    price REAL NOT NULL,
+
+    CONSTRAINT price_pk PRIMARY KEY (currency, timestamp)
+) strict;
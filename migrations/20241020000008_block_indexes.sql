@@ -0,0 +1,9 @@
+CREATE INDEX block_address_timestamp_idx ON block (address, timestamp);
+
+CREATE INDEX block_withdrawal_idx ON block (address, block_number) WHERE consensus_reward != '0';
+
+CREATE INDEX block_mev_idx ON block (address, block_number) WHERE mev_reward != '0';
+
+CREATE INDEX tx_address_block_idx ON tx (address, block_number);
+
+CREATE INDEX tx_trace_address_block_idx ON tx_trace (address, block_number);
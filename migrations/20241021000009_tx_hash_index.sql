@@ -0,0 +1,3 @@
+CREATE INDEX tx_hash_idx ON tx (tx_hash);
+
+CREATE INDEX tx_trace_hash_idx ON tx_trace (tx_hash);
@@ -0,0 +1,13 @@
+CREATE TABLE block_counterparty
+(
+    address TEXT NOT NULL,
+    block_number INT NOT NULL,
+    counterparty TEXT NOT NULL,
+    amount_in TEXT NOT NULL,
+    amount_out TEXT NOT NULL,
+
+    CONSTRAINT block_counterparty_pk PRIMARY KEY (address, block_number, counterparty),
+    CONSTRAINT block_counterparty_block_fk FOREIGN KEY (address, block_number)
+        REFERENCES block (address, block_number)
+        ON DELETE CASCADE
+) strict;
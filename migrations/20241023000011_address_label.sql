@@ -0,0 +1,10 @@
+CREATE TABLE address_label
+(
+    address TEXT NOT NULL,
+    name TEXT NOT NULL,
+    category TEXT NOT NULL,
+    notes TEXT NULL,
+    updated TEXT NOT NULL,
+
+    CONSTRAINT address_label_pk PRIMARY KEY (address)
+) strict;
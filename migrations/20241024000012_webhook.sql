@@ -0,0 +1,43 @@
+CREATE TABLE webhook
+(
+    id TEXT NOT NULL,
+    address TEXT NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- comma separated event names
+    events TEXT NOT NULL,
+    -- wei, incoming transfers below are not reported
+    min_transfer TEXT NOT NULL,
+    -- wei, withdrawals and MEV payments below are not reported
+    min_reward TEXT NOT NULL,
+    created TEXT NOT NULL,
+
+    CONSTRAINT webhook_pk PRIMARY KEY (id),
+    CONSTRAINT webhook_scan_fk FOREIGN KEY (address)
+        REFERENCES scan (address)
+        ON DELETE CASCADE
+) strict;
+
+CREATE INDEX webhook_address_idx ON webhook (address);
+
+CREATE TABLE webhook_delivery
+(
+    id TEXT NOT NULL,
+    webhook_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    -- pending, delivered or failed
+    status TEXT NOT NULL,
+    attempts INT NOT NULL,
+    last_error TEXT NULL,
+    next_attempt TEXT NOT NULL,
+    created TEXT NOT NULL,
+
+    CONSTRAINT webhook_delivery_pk PRIMARY KEY (id),
+    CONSTRAINT webhook_delivery_webhook_fk FOREIGN KEY (webhook_id)
+        REFERENCES webhook (id)
+        ON DELETE CASCADE
+) strict;
+
+CREATE INDEX webhook_delivery_due_idx ON webhook_delivery (status, next_attempt);
+CREATE INDEX webhook_delivery_webhook_idx ON webhook_delivery (webhook_id, created);
@@ -0,0 +1,32 @@
+CREATE TABLE mail_settings
+(
+    uid TEXT NOT NULL,
+    alerts INT NOT NULL,
+    weekly_digest INT NOT NULL,
+    -- wei, incoming transfers below are not reported
+    min_transfer TEXT NOT NULL,
+    -- alerts are sent only for events after that time
+    alerts_since TEXT NOT NULL,
+    updated TEXT NOT NULL,
+
+    CONSTRAINT mail_settings_pk PRIMARY KEY (uid),
+    CONSTRAINT mail_settings_user_fk FOREIGN KEY (uid)
+        REFERENCES users (uid)
+        ON DELETE CASCADE
+) strict;
+
+CREATE TABLE mail_sent
+(
+    uid TEXT NOT NULL,
+    -- what the mail was about, like transfer:<address>:<block> or digest:<year>-W<week>
+    topic TEXT NOT NULL,
+    sent TEXT NOT NULL,
+
+    CONSTRAINT mail_sent_pk PRIMARY KEY (uid, topic),
+    CONSTRAINT mail_sent_user_fk FOREIGN KEY (uid)
+        REFERENCES users (uid)
+        ON DELETE CASCADE
+) strict;
+
+CREATE INDEX block_updated_idx ON block (updated);
+CREATE INDEX failed_block_first_failed_idx ON failed_block (first_failed);
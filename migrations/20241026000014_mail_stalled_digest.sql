@@ -0,0 +1,5 @@
+-- only scans following the chain head are expected to keep moving
+ALTER TABLE scan ADD COLUMN follow INT NOT NULL DEFAULT 0;
+-- digests are sent only for weeks ending after that time
+ALTER TABLE mail_settings ADD COLUMN digest_since TEXT NOT NULL DEFAULT '';
+UPDATE mail_settings SET digest_since = updated;
@@ -0,0 +1,11 @@
+-- found transfers do not add up to the balance change, inspecting again does not change that
+ALTER TABLE block ADD COLUMN reconciled INT NOT NULL DEFAULT 1;
+
+UPDATE block SET reconciled = 0
+WHERE EXISTS (
+    SELECT 1 FROM failed_block
+    WHERE failed_block.address = block.address
+    AND failed_block.block_number = block.block_number
+    AND failed_block.error = 'Sum diff does not match'
+);
+DELETE FROM failed_block WHERE error = 'Sum diff does not match';
@@ -1 +1,2 @@
+pub mod notification;
 pub mod user;
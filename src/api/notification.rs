@@ -0,0 +1,100 @@
+use crate::db::model::mail::MailSettingsDbObj;
+use crate::db::model::UserDbObj;
+use crate::db::ops::mail::{get_mail_settings, upsert_mail_settings};
+use crate::ServerData;
+use actix_session::Session;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, Responder};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MailSettingsInput {
+    pub alerts: bool,
+    pub weekly_digest: bool,
+    /// Wei, incoming transfers below are not reported
+    #[serde(default)]
+    pub min_transfer: Option<String>,
+}
+
+fn default_settings(uid: &str) -> MailSettingsDbObj {
+    let now = chrono::Utc::now();
+    MailSettingsDbObj {
+        uid: uid.to_string(),
+        alerts: false,
+        weekly_digest: false,
+        min_transfer: "0".to_string(),
+        alerts_since: now,
+        digest_since: now,
+        updated: now,
+    }
+}
+
+pub async fn handle_get_mail_settings(
+    data: Data<Box<ServerData>>,
+    session: Session,
+) -> impl Responder {
+    let Some(user) = session.get::<UserDbObj>("user").unwrap_or(None) else {
+        return HttpResponse::Unauthorized().body("Not logged in");
+    };
+    let db_conn = data.db_connection.lock().await;
+    match get_mail_settings(&*db_conn, &user.uid).await {
+        Ok(settings) => {
+            HttpResponse::Ok().json(settings.unwrap_or_else(|| default_settings(&user.uid)))
+        }
+        Err(err) => {
+            log::error!("Error getting mail settings: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn handle_put_mail_settings(
+    data: Data<Box<ServerData>>,
+    input: web::Json<MailSettingsInput>,
+    session: Session,
+) -> impl Responder {
+    let Some(user) = session.get::<UserDbObj>("user").unwrap_or(None) else {
+        return HttpResponse::Unauthorized().body("Not logged in");
+    };
+    let min_transfer = match input.min_transfer.as_deref().unwrap_or("0").parse::<u128>() {
+        Ok(min_transfer) => min_transfer.to_string(),
+        Err(_) => return HttpResponse::BadRequest().body("Invalid minTransfer"),
+    };
+    let db_conn = data.db_connection.lock().await;
+    let prev = match get_mail_settings(&*db_conn, &user.uid).await {
+        Ok(prev) => prev.unwrap_or_else(|| default_settings(&user.uid)),
+        Err(err) => {
+            log::error!("Error getting mail settings: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let now = chrono::Utc::now();
+    let settings = MailSettingsDbObj {
+        uid: user.uid,
+        alerts: input.alerts,
+        weekly_digest: input.weekly_digest,
+        min_transfer,
+        // no alerts about events from before opting in
+        alerts_since: if input.alerts && !prev.alerts {
+            now
+        } else {
+            prev.alerts_since
+        },
+        // no digest of a week that ended before opting in
+        digest_since: if input.weekly_digest && !prev.weekly_digest {
+            now
+        } else {
+            prev.digest_since
+        },
+        updated: now,
+    };
+    match upsert_mail_settings(&*db_conn, &settings).await {
+        Ok(settings) => HttpResponse::Ok().json(settings),
+        Err(err) => {
+            log::error!("Error saving mail settings: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::db::model::UserDbObj;
 use crate::db::ops::{get_user, update_user_password};
+use crate::metrics;
 use crate::ServerData;
 use actix_session::Session;
 use actix_web::web;
@@ -92,6 +93,7 @@ pub async fn handle_login(
     tokio::time::sleep(Duration::from_millis(random_duration)).await;
 
     if !ALLOWED_EMAILS.contains(&login.email) {
+        metrics::inc_login(false);
         return HttpResponse::Unauthorized().body("This email is not allowed");
     }
 
@@ -104,6 +106,7 @@ pub async fn handle_login(
         Ok(usr) => usr,
         Err(err) => {
             log::error!("Error getting user: {}", err);
+            metrics::inc_login(false);
             return HttpResponse::Unauthorized().body("Invalid email or password");
         }
     };
@@ -111,9 +114,11 @@ pub async fn handle_login(
     if usr.pass_hash == key {
         log::info!("User {} logged in", login.email);
         session.insert("user", &usr).unwrap();
+        metrics::inc_login(true);
 
         return HttpResponse::Ok().json(usr);
     }
+    metrics::inc_login(false);
     HttpResponse::Unauthorized().body("Invalid email or password")
 }
 
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// Name of a known address, like an exchange, a block builder or our own wallet
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressLabelDbObj {
+    pub address: String,
+    pub name: String,
+    /// See LabelCategory
+    pub category: String,
+    pub notes: Option<String>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MailSettingsDbObj {
+    pub uid: String,
+    /// Large transfer, anomaly and stalled scan alerts
+    pub alerts: bool,
+    pub weekly_digest: bool,
+    /// Wei, incoming transfers below are not reported
+    pub min_transfer: String,
+    /// Alerts are sent only for events after that time
+    pub alerts_since: chrono::DateTime<chrono::Utc>,
+    /// Digests are sent only for weeks ending after that time
+    pub digest_since: chrono::DateTime<chrono::Utc>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+/// User with mail settings, as needed by the mailer
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MailRecipientDbObj {
+    pub email: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub settings: MailSettingsDbObj,
+}
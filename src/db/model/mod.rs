@@ -1,4 +1,8 @@
+pub mod label;
+pub mod mail;
+pub mod price;
 pub mod transaction;
+pub mod webhook;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
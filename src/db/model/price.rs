@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Price of one ETH in given fiat currency, valid from timestamp until the next price
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceDbObj {
+    pub currency: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub price: f64,
+}
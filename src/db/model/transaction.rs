@@ -8,6 +8,12 @@ pub struct ScanDbObj {
     pub first_block_timestamp: chrono::DateTime<chrono::Utc>,
     pub next_block_number: i64,
     pub next_block_timestamp: chrono::DateTime<chrono::Utc>,
+    /// Finality policy used to pick the scan boundary, for example `finalized` or `depth:100`
+    pub finality_policy: Option<String>,
+    /// Last block considered final during the most recent scan
+    pub finality_block_number: Option<i64>,
+    /// Scan keeps following the chain head, other scans stop at their end block
+    pub follow: bool,
 }
 
 #[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
@@ -25,6 +31,63 @@ pub struct BlockDbObj {
     pub block_reward: String,
     pub amount_incoming: String,
     pub amount_outgoing: String,
+    /// How the internal calls were obtained, see TraceMode
+    pub trace_mode: String,
+    /// Balance change not explained by found transfers, expected in receipt only mode
+    pub unexplained_value: Option<String>,
+    /// False when found transfers do not add up to the balance change
+    pub reconciled: bool,
+}
+
+/// Wei in one ETH
+pub const WEI_IN_ETH: i128 = 1_000_000_000_000_000_000;
+
+/// Wei amount stored as decimal string, 0 with a warning when it does not parse
+pub fn wei(value: &str) -> i128 {
+    value.parse().unwrap_or_else(|e| {
+        log::warn!("Invalid wei amount {:?}, using 0: {}", value, e);
+        0
+    })
+}
+
+/// Wei as ETH with 6 decimals
+pub fn format_eth(wei: i128) -> String {
+    let micro_eth = wei / (WEI_IN_ETH / 1_000_000);
+    let sign = if micro_eth < 0 { "-" } else { "" };
+    let micro_eth = micro_eth.abs();
+    format!(
+        "{}{}.{:06}",
+        sign,
+        micro_eth / 1_000_000,
+        micro_eth % 1_000_000
+    )
+}
+
+#[cfg(test)]
+impl BlockDbObj {
+    /// Block without any value moved, set amounts with struct update syntax
+    pub fn test_block(address: &str, block_number: i64, timestamp: &str) -> Self {
+        let timestamp = chrono::DateTime::parse_from_rfc3339(timestamp)
+            .unwrap()
+            .to_utc();
+        BlockDbObj {
+            address: address.to_string(),
+            block_number,
+            timestamp,
+            balance: "0".to_string(),
+            balance_diff: "0".to_string(),
+            updated: timestamp,
+            block_miner: String::new(),
+            consensus_reward: "0".to_string(),
+            mev_reward: "0".to_string(),
+            block_reward: "0".to_string(),
+            amount_incoming: "0".to_string(),
+            amount_outgoing: "0".to_string(),
+            trace_mode: "trace_block".to_string(),
+            unexplained_value: None,
+            reconciled: true,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
@@ -50,3 +113,49 @@ pub struct TxTraceDbObj {
     pub value: String,
     pub gas_used: String,
 }
+
+/// Value moved between the address and one counterparty in a block
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockCounterpartyDbObj {
+    pub address: String,
+    pub block_number: i64,
+    pub counterparty: String,
+    /// Received from the counterparty in wei
+    pub amount_in: String,
+    /// Sent to the counterparty in wei
+    pub amount_out: String,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedBlockDbObj {
+    pub address: String,
+    pub block_number: i64,
+    pub error: String,
+    pub attempts: i64,
+    pub first_failed: chrono::DateTime<chrono::Utc>,
+    pub last_attempt: chrono::DateTime<chrono::Utc>,
+    pub next_retry: chrono::DateTime<chrono::Utc>,
+}
+
+/// Blocks [block_start, block_end) of the scan, all handled in the same way
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageRangeDbObj {
+    pub address: String,
+    pub block_start: i64,
+    pub block_end: i64,
+    /// inspected, skipped_unchanged or failed
+    pub status: String,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+#[test]
+fn wei_test() {
+    assert_eq!(wei("-42"), -42);
+    assert_eq!(wei("1.5"), 0);
+    assert_eq!(wei(""), 0);
+    assert_eq!(format_eth(WEI_IN_ETH * 3 / 2), "1.500000");
+    assert_eq!(format_eth(-2_500_000_000_000_000), "-0.002500");
+}
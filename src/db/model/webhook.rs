@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDbObj {
+    pub id: String,
+    pub address: String,
+    pub url: String,
+    /// HMAC key of the payload signature, shown only when the webhook is created
+    #[serde(skip)]
+    pub secret: String,
+    /// Comma separated, see WebhookEvent
+    pub events: String,
+    pub min_transfer: String,
+    pub min_reward: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryDbObj {
+    pub id: String,
+    pub webhook_id: String,
+    pub event: String,
+    /// JSON body sent to the webhook
+    pub payload: String,
+    /// pending, delivered or failed
+    pub status: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
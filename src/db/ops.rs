@@ -1,5 +1,9 @@
+pub mod label;
+pub mod mail;
+pub mod price;
 pub mod transaction;
 mod user;
+pub mod webhook;
 
 pub use user::*;
 
@@ -0,0 +1,71 @@
+use crate::db::model::label::AddressLabelDbObj;
+use crate::metrics::db_query_timer;
+use sqlx::{Executor, Sqlite};
+
+pub async fn get_address_labels<'c, E>(conn: E) -> Result<Vec<AddressLabelDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_address_labels");
+    let res =
+        sqlx::query_as::<_, AddressLabelDbObj>(r"SELECT * FROM address_label ORDER BY address;")
+            .fetch_all(conn)
+            .await?;
+    Ok(res)
+}
+
+pub async fn get_address_label<'c, E>(
+    conn: E,
+    address: &str,
+) -> Result<Option<AddressLabelDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_address_label");
+    let res =
+        sqlx::query_as::<_, AddressLabelDbObj>(r"SELECT * FROM address_label WHERE address = $1;")
+            .bind(address)
+            .fetch_optional(conn)
+            .await?;
+    Ok(res)
+}
+
+pub async fn upsert_address_label<'c, E>(
+    conn: E,
+    label: &AddressLabelDbObj,
+) -> Result<AddressLabelDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("upsert_address_label");
+    let res = sqlx::query_as::<_, AddressLabelDbObj>(
+        r"INSERT INTO address_label
+(address, name, category, notes, updated)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (address) DO UPDATE SET
+name = excluded.name, category = excluded.category, notes = excluded.notes, updated = excluded.updated
+RETURNING *;
+",
+    )
+    .bind(&label.address)
+    .bind(&label.name)
+    .bind(&label.category)
+    .bind(&label.notes)
+    .bind(label.updated)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Returns false when there was no label
+pub async fn delete_address_label<'c, E>(conn: E, address: &str) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_address_label");
+    let res = sqlx::query(r"DELETE FROM address_label WHERE address = $1;")
+        .bind(address)
+        .execute(conn)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
@@ -0,0 +1,137 @@
+use crate::db::model::mail::{MailRecipientDbObj, MailSettingsDbObj};
+use crate::db::model::transaction::{BlockDbObj, FailedBlockDbObj};
+use crate::metrics::db_query_timer;
+use sqlx::{Executor, Sqlite};
+
+pub async fn get_mail_settings<'c, E>(
+    conn: E,
+    uid: &str,
+) -> Result<Option<MailSettingsDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_mail_settings");
+    let res =
+        sqlx::query_as::<_, MailSettingsDbObj>(r"SELECT * FROM mail_settings WHERE uid = $1;")
+            .bind(uid)
+            .fetch_optional(conn)
+            .await?;
+    Ok(res)
+}
+
+pub async fn upsert_mail_settings<'c, E>(
+    conn: E,
+    settings: &MailSettingsDbObj,
+) -> Result<MailSettingsDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("upsert_mail_settings");
+    let res = sqlx::query_as::<_, MailSettingsDbObj>(
+        r"INSERT INTO mail_settings
+(uid, alerts, weekly_digest, min_transfer, alerts_since, digest_since, updated)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (uid) DO UPDATE SET
+alerts = excluded.alerts, weekly_digest = excluded.weekly_digest, min_transfer = excluded.min_transfer,
+alerts_since = excluded.alerts_since, digest_since = excluded.digest_since, updated = excluded.updated
+RETURNING *;
+",
+    )
+    .bind(&settings.uid)
+    .bind(settings.alerts)
+    .bind(settings.weekly_digest)
+    .bind(&settings.min_transfer)
+    .bind(settings.alerts_since)
+    .bind(settings.digest_since)
+    .bind(settings.updated)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Users who opted in to alerts or the weekly digest
+pub async fn get_mail_recipients<'c, E>(conn: E) -> Result<Vec<MailRecipientDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_mail_recipients");
+    let res = sqlx::query_as::<_, MailRecipientDbObj>(
+        r"SELECT users.email, mail_settings.*
+FROM mail_settings JOIN users ON users.uid = mail_settings.uid
+WHERE mail_settings.alerts OR mail_settings.weekly_digest;
+",
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn is_mail_sent<'c, E>(conn: E, uid: &str, topic: &str) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("is_mail_sent");
+    let res = sqlx::query_scalar::<_, i64>(
+        r"SELECT COUNT(*) FROM mail_sent WHERE uid = $1 AND topic = $2;",
+    )
+    .bind(uid)
+    .bind(topic)
+    .fetch_one(conn)
+    .await?;
+    Ok(res > 0)
+}
+
+pub async fn insert_mail_sent<'c, E>(
+    conn: E,
+    uid: &str,
+    topic: &str,
+    sent: chrono::DateTime<chrono::Utc>,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_mail_sent");
+    sqlx::query(r"INSERT OR IGNORE INTO mail_sent (uid, topic, sent) VALUES ($1, $2, $3);")
+        .bind(uid)
+        .bind(topic)
+        .bind(sent)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Blocks of all scans mined and saved after the given time, so backfilled history is left out
+pub async fn get_blocks_updated_since<'c, E>(
+    conn: E,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<BlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_blocks_updated_since");
+    let res = sqlx::query_as::<_, BlockDbObj>(
+        r"SELECT * FROM block WHERE updated > $1 AND timestamp > $1 ORDER BY updated;",
+    )
+    .bind(since)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Blocks of all scans that failed for the first time after the given time
+pub async fn get_blocks_failed_since<'c, E>(
+    conn: E,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<FailedBlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_blocks_failed_since");
+    let res = sqlx::query_as::<_, FailedBlockDbObj>(
+        r"SELECT * FROM failed_block WHERE first_failed > $1 ORDER BY first_failed;",
+    )
+    .bind(since)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
@@ -0,0 +1,46 @@
+use crate::db::model::price::PriceDbObj;
+use crate::metrics::db_query_timer;
+use sqlx::{Executor, Sqlite};
+
+pub async fn upsert_price<'c, E>(conn: E, price: &PriceDbObj) -> Result<PriceDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("upsert_price");
+    let res = sqlx::query_as::<_, PriceDbObj>(
+        r"INSERT INTO price
+(currency, timestamp, price)
+VALUES ($1, $2, $3)
+ON CONFLICT (currency, timestamp) DO UPDATE SET price = excluded.price
+RETURNING *;
+",
+    )
+    .bind(&price.currency)
+    .bind(price.timestamp)
+    .bind(price.price)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Prices with timestamp in [from, to], ordered by timestamp
+pub async fn get_prices<'c, E>(
+    conn: E,
+    currency: &str,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<PriceDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_prices");
+    let res = sqlx::query_as::<_, PriceDbObj>(
+        r"SELECT * FROM price WHERE currency = $1 AND timestamp >= $2 AND timestamp <= $3 ORDER BY timestamp;",
+    )
+    .bind(currency)
+    .bind(from)
+    .bind(to)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
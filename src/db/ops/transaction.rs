@@ -1,11 +1,20 @@
-use crate::db::model::transaction::{BlockDbObj, ScanDbObj, TxDbObj, TxTraceDbObj};
-use sqlx::SqlitePool;
+use crate::db::model::transaction::{
+    BlockCounterpartyDbObj, BlockDbObj, CoverageRangeDbObj, FailedBlockDbObj, ScanDbObj, TxDbObj,
+    TxTraceDbObj,
+};
+use crate::metrics::db_query_timer;
+use futures_util::stream::BoxStream;
+use sqlx::{Executor, Sqlite};
 
-pub async fn delete_block_tx(
-    conn: &SqlitePool,
+pub async fn delete_block_tx<'c, E>(
+    conn: E,
     address: String,
     block_number: i64,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_block_tx");
     let _res = sqlx::query(r"DELETE FROM block WHERE block_number = $1 and address = $2;")
         .bind(block_number)
         .bind(address)
@@ -14,14 +23,22 @@ pub async fn delete_block_tx(
     Ok(())
 }
 
-pub async fn get_all_scans(conn: &SqlitePool) -> Result<Vec<ScanDbObj>, sqlx::Error> {
+pub async fn get_all_scans<'c, E>(conn: E) -> Result<Vec<ScanDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_all_scans");
     let res = sqlx::query_as::<_, ScanDbObj>(r"SELECT * FROM scan;")
         .fetch_all(conn)
         .await?;
     Ok(res)
 }
 
-pub async fn get_scan(conn: &SqlitePool, address: &str) -> Result<Option<ScanDbObj>, sqlx::Error> {
+pub async fn get_scan<'c, E>(conn: E, address: &str) -> Result<Option<ScanDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_scan");
     let res = sqlx::query_as::<_, ScanDbObj>(r"SELECT * FROM scan WHERE address = $1;")
         .bind(address)
         .fetch_optional(conn)
@@ -29,11 +46,15 @@ pub async fn get_scan(conn: &SqlitePool, address: &str) -> Result<Option<ScanDbO
     Ok(res)
 }
 
-pub async fn insert_scan(conn: &SqlitePool, scan: &ScanDbObj) -> Result<ScanDbObj, sqlx::Error> {
+pub async fn insert_scan<'c, E>(conn: E, scan: &ScanDbObj) -> Result<ScanDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_scan");
     let res = sqlx::query_as::<_, ScanDbObj>(
         r"INSERT INTO scan
-    (address, first_block_number, first_block_timestamp, next_block_number, next_block_timestamp)
-    VALUES ($1, $2, $3, $4, $5) RETURNING *;
+    (address, first_block_number, first_block_timestamp, next_block_number, next_block_timestamp, finality_policy, finality_block_number, follow)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *;
     ",
     )
     .bind(&scan.address)
@@ -41,12 +62,19 @@ pub async fn insert_scan(conn: &SqlitePool, scan: &ScanDbObj) -> Result<ScanDbOb
     .bind(scan.first_block_timestamp)
     .bind(scan.next_block_number)
     .bind(scan.next_block_timestamp)
+    .bind(&scan.finality_policy)
+    .bind(scan.finality_block_number)
+    .bind(scan.follow)
     .fetch_one(conn)
     .await?;
     Ok(res)
 }
 
-pub async fn delete_scan(conn: &SqlitePool, address: &str) -> Result<(), sqlx::Error> {
+pub async fn delete_scan<'c, E>(conn: E, address: &str) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_scan");
     let _res = sqlx::query(r"DELETE FROM scan WHERE address = $1;")
         .bind(address)
         .execute(conn)
@@ -54,43 +82,382 @@ pub async fn delete_scan(conn: &SqlitePool, address: &str) -> Result<(), sqlx::E
     Ok(())
 }
 
-pub async fn update_scan(conn: &SqlitePool, scan: &ScanDbObj) -> Result<ScanDbObj, sqlx::Error> {
+pub async fn update_scan<'c, E>(conn: E, scan: &ScanDbObj) -> Result<ScanDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("update_scan");
     let res = sqlx::query_as::<_, ScanDbObj>(
         r"UPDATE scan
     SET
     first_block_number = $1,
     first_block_timestamp = $2,
     next_block_number = $3,
-    next_block_timestamp = $4
-    WHERE address = $5 RETURNING *;
+    next_block_timestamp = $4,
+    finality_policy = $5,
+    finality_block_number = $6,
+    follow = $7
+    WHERE address = $8 RETURNING *;
     ",
     )
     .bind(scan.first_block_number)
     .bind(scan.first_block_timestamp)
     .bind(scan.next_block_number)
     .bind(scan.next_block_timestamp)
+    .bind(&scan.finality_policy)
+    .bind(scan.finality_block_number)
+    .bind(scan.follow)
     .bind(&scan.address)
     .fetch_one(conn)
     .await?;
     Ok(res)
 }
 
-pub async fn get_blocks(conn: &SqlitePool, address: &str) -> Result<Vec<BlockDbObj>, sqlx::Error> {
-    let res = sqlx::query_as::<_, BlockDbObj>(r"SELECT * FROM block WHERE address = $1;")
-        .bind(address)
+pub async fn get_blocks<'c, E>(conn: E, address: &str) -> Result<Vec<BlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_blocks");
+    let res = sqlx::query_as::<_, BlockDbObj>(
+        r"SELECT * FROM block WHERE address = $1 ORDER BY block_number;",
+    )
+    .bind(address)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Block number range [block_start, block_end) and timestamp range [from, to) of exported rows
+#[derive(Debug, Clone, Default)]
+pub struct BlockFilter {
+    pub block_start: Option<i64>,
+    pub block_end: Option<i64>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+const BLOCK_FILTER_CONDITION: &str = r"block.address = $1
+AND ($2 IS NULL OR block.block_number >= $2)
+AND ($3 IS NULL OR block.block_number < $3)
+AND ($4 IS NULL OR block.timestamp >= $4)
+AND ($5 IS NULL OR block.timestamp < $5)";
+
+fn filtered_query<'q, T>(
+    sql: &'q str,
+    address: &str,
+    filter: &BlockFilter,
+) -> sqlx::query::QueryAs<'q, Sqlite, T, sqlx::sqlite::SqliteArguments<'q>>
+where
+    T: for<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow>,
+{
+    sqlx::query_as::<_, T>(sql)
+        .bind(address.to_string())
+        .bind(filter.block_start)
+        .bind(filter.block_end)
+        .bind(filter.from)
+        .bind(filter.to)
+}
+
+lazy_static::lazy_static! {
+    static ref STREAM_BLOCKS_SQL: String = format!(
+        "SELECT * FROM block WHERE {BLOCK_FILTER_CONDITION} ORDER BY block_number;"
+    );
+    static ref STREAM_WITHDRAWALS_SQL: String = format!(
+        "SELECT * FROM block WHERE {BLOCK_FILTER_CONDITION} AND consensus_reward != '0' ORDER BY block_number;"
+    );
+    static ref STREAM_TXS_SQL: String = format!(
+        "SELECT tx.* FROM tx JOIN block ON block.address = tx.address AND block.block_number = tx.block_number
+WHERE {BLOCK_FILTER_CONDITION} ORDER BY tx.block_number, tx.block_index;"
+    );
+    static ref GET_TXS_SQL: String = format!(
+        "SELECT tx.* FROM tx JOIN block ON block.address = tx.address AND block.block_number = tx.block_number
+WHERE {BLOCK_FILTER_CONDITION}
+AND ($6 IS NULL OR tx.block_number > $6 OR (tx.block_number = $6 AND tx.block_index > $7))
+ORDER BY tx.block_number, tx.block_index LIMIT $8;"
+    );
+    static ref GET_COUNTERPARTIES_SQL: String = format!(
+        "SELECT block_counterparty.* FROM block_counterparty JOIN block ON block.address = block_counterparty.address AND block.block_number = block_counterparty.block_number
+WHERE {BLOCK_FILTER_CONDITION} ORDER BY block_counterparty.block_number;"
+    );
+    static ref STREAM_TX_TRACES_SQL: String = format!(
+        "SELECT tx_trace.* FROM tx_trace JOIN block ON block.address = tx_trace.address AND block.block_number = tx_trace.block_number
+WHERE {BLOCK_FILTER_CONDITION} ORDER BY tx_trace.block_number, tx_trace.block_index, tx_trace.trace_index;"
+    );
+}
+
+/// Rows are fetched as the stream is consumed, so exports do not have to fit in memory
+pub fn stream_blocks<'e, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+) -> BoxStream<'e, Result<BlockDbObj, sqlx::Error>>
+where
+    E: Executor<'e, Database = Sqlite> + 'e,
+{
+    filtered_query(&STREAM_BLOCKS_SQL, address, filter).fetch(conn)
+}
+
+/// Blocks with consensus layer withdrawal
+pub fn stream_withdrawal_blocks<'e, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+) -> BoxStream<'e, Result<BlockDbObj, sqlx::Error>>
+where
+    E: Executor<'e, Database = Sqlite> + 'e,
+{
+    filtered_query(&STREAM_WITHDRAWALS_SQL, address, filter).fetch(conn)
+}
+
+pub fn stream_txs<'e, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+) -> BoxStream<'e, Result<TxDbObj, sqlx::Error>>
+where
+    E: Executor<'e, Database = Sqlite> + 'e,
+{
+    filtered_query(&STREAM_TXS_SQL, address, filter).fetch(conn)
+}
+
+pub fn stream_tx_traces<'e, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+) -> BoxStream<'e, Result<TxTraceDbObj, sqlx::Error>>
+where
+    E: Executor<'e, Database = Sqlite> + 'e,
+{
+    filtered_query(&STREAM_TX_TRACES_SQL, address, filter).fetch(conn)
+}
+
+/// Kind of value moved in a block, used to filter blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockActivity {
+    Mev,
+    Withdrawals,
+    Transfers,
+}
+
+impl BlockActivity {
+    fn condition(&self) -> &'static str {
+        match self {
+            BlockActivity::Mev => "mev_reward != '0'",
+            BlockActivity::Withdrawals => "consensus_reward != '0'",
+            BlockActivity::Transfers => "(amount_incoming != '0' OR amount_outgoing != '0')",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockSort {
+    #[default]
+    BlockNumber,
+    BalanceDiff,
+    ConsensusReward,
+    MevReward,
+    AmountIncoming,
+    AmountOutgoing,
+}
+
+impl BlockSort {
+    fn column(&self) -> &'static str {
+        match self {
+            BlockSort::BlockNumber => "block_number",
+            BlockSort::BalanceDiff => "balance_diff",
+            BlockSort::ConsensusReward => "consensus_reward",
+            BlockSort::MevReward => "mev_reward",
+            BlockSort::AmountIncoming => "amount_incoming",
+            BlockSort::AmountOutgoing => "amount_outgoing",
+        }
+    }
+}
+
+/// Digits integers are padded to for sorting, enough for any U256
+const SORT_KEY_DIGITS: usize = 78;
+
+/// SQL expression turning a column of decimal integers into text sorted in numeric order:
+/// 1 and the zero padded digits for non-negative values,
+/// 0 and the padded digits complemented to 9 for negative ones
+fn sort_key_expression(column: &str) -> String {
+    let zeros = "0".repeat(SORT_KEY_DIGITS);
+    let non_negative = format!("'1' || substr('{zeros}' || {column}, -{SORT_KEY_DIGITS})");
+    let mut negative = format!("substr('{zeros}' || substr({column}, 2), -{SORT_KEY_DIGITS})");
+    // through letters, so no digit is replaced twice
+    for digit in 0..10_u8 {
+        negative = format!(
+            "replace({negative}, '{digit}', '{}')",
+            (b'a' + digit) as char
+        );
+    }
+    for digit in 0..10_u8 {
+        negative = format!(
+            "replace({negative}, '{}', '{}')",
+            (b'a' + digit) as char,
+            9 - digit
+        );
+    }
+    format!(
+        "(CASE WHEN substr({column}, 1, 1) = '-' THEN '0' || {negative} ELSE {non_negative} END)"
+    )
+}
+
+/// Sort key of a non-negative amount, as given by sort_key_expression
+fn amount_sort_key(amount: u128) -> String {
+    format!("1{:0>width$}", amount, width = SORT_KEY_DIGITS)
+}
+
+/// Position after the last returned block: its sort key and block number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocksCursor {
+    pub sort_value: String,
+    pub block_number: i64,
+}
+
+impl std::fmt::Display for BlocksCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.sort_value, self.block_number)
+    }
+}
+
+impl std::str::FromStr for BlocksCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sort_value, block_number) =
+            s.split_once(':').ok_or(format!("Invalid cursor {}", s))?;
+        Ok(BlocksCursor {
+            sort_value: sort_value
+                .parse()
+                .map_err(|_| format!("Invalid cursor {}", s))?,
+            block_number: block_number
+                .parse()
+                .map_err(|_| format!("Invalid cursor {}", s))?,
+        })
+    }
+}
+
+/// Position after the last returned transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxsCursor {
+    pub block_number: i64,
+    pub block_index: i64,
+}
+
+impl std::fmt::Display for TxsCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.block_number, self.block_index)
+    }
+}
+
+impl std::str::FromStr for TxsCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (block_number, block_index) =
+            s.split_once(':').ok_or(format!("Invalid cursor {}", s))?;
+        Ok(TxsCursor {
+            block_number: block_number
+                .parse()
+                .map_err(|_| format!("Invalid cursor {}", s))?,
+            block_index: block_index
+                .parse()
+                .map_err(|_| format!("Invalid cursor {}", s))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlocksPageQuery {
+    pub filter: BlockFilter,
+    /// Largest of rewards and transferred amounts has to be at least that (in wei)
+    pub min_amount: Option<u128>,
+    /// Blocks with any of given activities, all blocks when empty
+    pub activities: Vec<BlockActivity>,
+    pub sort: BlockSort,
+    pub descending: bool,
+    pub cursor: Option<BlocksCursor>,
+    pub limit: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct SortedBlock {
+    #[sqlx(flatten)]
+    block: BlockDbObj,
+    sort_value: String,
+}
+
+/// One page of blocks and cursor of the next one, if there are more blocks
+pub async fn get_blocks_page<'c, E>(
+    conn: E,
+    address: &str,
+    query: &BlocksPageQuery,
+) -> Result<(Vec<BlockDbObj>, Option<BlocksCursor>), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_blocks_page");
+    let sort_expression = sort_key_expression(query.sort.column());
+    let amounts = [
+        "consensus_reward",
+        "mev_reward",
+        "amount_incoming",
+        "amount_outgoing",
+    ]
+    .map(sort_key_expression)
+    .join(", ");
+    let (direction, comparison) = if query.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+    let activities = if query.activities.is_empty() {
+        String::new()
+    } else {
+        let conditions: Vec<&str> = query.activities.iter().map(|a| a.condition()).collect();
+        format!("AND ({})", conditions.join(" OR "))
+    };
+    let sql = format!(
+        r"SELECT *, {sort_expression} AS sort_value FROM block
+WHERE {BLOCK_FILTER_CONDITION}
+AND ($6 IS NULL OR MAX({amounts}) >= $6)
+AND ($7 IS NULL OR {sort_expression} {comparison} $7 OR ({sort_expression} = $7 AND block_number {comparison} $8))
+{activities}
+ORDER BY {sort_expression} {direction}, block_number {direction} LIMIT $9;"
+    );
+    let mut rows = filtered_query::<SortedBlock>(&sql, address, &query.filter)
+        .bind(query.min_amount.map(amount_sort_key))
+        .bind(
+            query
+                .cursor
+                .as_ref()
+                .map(|cursor| cursor.sort_value.clone()),
+        )
+        .bind(query.cursor.as_ref().map(|cursor| cursor.block_number))
+        // one more row tells if there is a next page
+        .bind(query.limit + 1)
         .fetch_all(conn)
         .await?;
-    Ok(res)
+    let mut next_cursor = None;
+    if rows.len() as i64 > query.limit {
+        rows.truncate(query.limit as usize);
+        next_cursor = rows.last().map(|row| BlocksCursor {
+            sort_value: row.sort_value.clone(),
+            block_number: row.block.block_number,
+        });
+    }
+    Ok((rows.into_iter().map(|row| row.block).collect(), next_cursor))
 }
 
-pub async fn insert_block(
-    conn: &SqlitePool,
-    block: &BlockDbObj,
-) -> Result<BlockDbObj, sqlx::Error> {
+pub async fn insert_block<'c, E>(conn: E, block: &BlockDbObj) -> Result<BlockDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_block");
     let res = sqlx::query_as::<_, BlockDbObj>(
         r"INSERT INTO block
-    (address, block_number, timestamp, balance, balance_diff, updated, block_miner, consensus_reward, mev_reward, block_reward, amount_incoming, amount_outgoing)
-    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING *;
+    (address, block_number, timestamp, balance, balance_diff, updated, block_miner, consensus_reward, mev_reward, block_reward, amount_incoming, amount_outgoing, trace_mode, unexplained_value, reconciled)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING *;
     ",
     )
         .bind(&block.address)
@@ -105,12 +472,177 @@ pub async fn insert_block(
     .bind(&block.block_reward)
     .bind(&block.amount_incoming)
     .bind(&block.amount_outgoing)
+    .bind(&block.trace_mode)
+    .bind(&block.unexplained_value)
+    .bind(block.reconciled)
     .fetch_one(conn)
     .await?;
     Ok(res)
 }
 
-pub async fn insert_tx(conn: &SqlitePool, tx: &TxDbObj) -> Result<TxDbObj, sqlx::Error> {
+pub async fn get_block<'c, E>(
+    conn: E,
+    address: &str,
+    block_number: i64,
+) -> Result<Option<BlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_block");
+    let res = sqlx::query_as::<_, BlockDbObj>(
+        r"SELECT * FROM block WHERE address = $1 AND block_number = $2;",
+    )
+    .bind(address)
+    .bind(block_number)
+    .fetch_optional(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Transactions after the cursor in block order, with cursor of the next page if there is one
+pub async fn get_txs<'c, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+    cursor: Option<TxsCursor>,
+    limit: i64,
+) -> Result<(Vec<TxDbObj>, Option<TxsCursor>), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_txs");
+    let mut rows = filtered_query::<TxDbObj>(&GET_TXS_SQL, address, filter)
+        .bind(cursor.map(|cursor| cursor.block_number))
+        .bind(cursor.map(|cursor| cursor.block_index))
+        // one more row tells if there is a next page
+        .bind(limit + 1)
+        .fetch_all(conn)
+        .await?;
+    let mut next_cursor = None;
+    if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        next_cursor = rows.last().map(|tx| TxsCursor {
+            block_number: tx.block_number,
+            block_index: tx.block_index,
+        });
+    }
+    Ok((rows, next_cursor))
+}
+
+pub async fn get_block_txs<'c, E>(
+    conn: E,
+    address: &str,
+    block_number: i64,
+) -> Result<Vec<TxDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_block_txs");
+    let res = sqlx::query_as::<_, TxDbObj>(
+        r"SELECT * FROM tx WHERE address = $1 AND block_number = $2 ORDER BY block_index;",
+    )
+    .bind(address)
+    .bind(block_number)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn get_block_tx_traces<'c, E>(
+    conn: E,
+    address: &str,
+    block_number: i64,
+) -> Result<Vec<TxTraceDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_block_tx_traces");
+    let res = sqlx::query_as::<_, TxTraceDbObj>(
+        r"SELECT * FROM tx_trace WHERE address = $1 AND block_number = $2 ORDER BY block_index, trace_index;",
+    )
+    .bind(address)
+    .bind(block_number)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Transaction saved for every scanned address it touched
+pub async fn get_txs_by_hash<'c, E>(conn: E, tx_hash: &str) -> Result<Vec<TxDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_txs_by_hash");
+    let res =
+        sqlx::query_as::<_, TxDbObj>(r"SELECT * FROM tx WHERE tx_hash = $1 ORDER BY address;")
+            .bind(tx_hash)
+            .fetch_all(conn)
+            .await?;
+    Ok(res)
+}
+
+pub async fn get_tx_traces_by_hash<'c, E>(
+    conn: E,
+    tx_hash: &str,
+) -> Result<Vec<TxTraceDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_tx_traces_by_hash");
+    let res = sqlx::query_as::<_, TxTraceDbObj>(
+        r"SELECT * FROM tx_trace WHERE tx_hash = $1 ORDER BY address, trace_index;",
+    )
+    .bind(tx_hash)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Counterparty flows of blocks matching the filter
+pub async fn get_block_counterparties<'c, E>(
+    conn: E,
+    address: &str,
+    filter: &BlockFilter,
+) -> Result<Vec<BlockCounterpartyDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_block_counterparties");
+    let res = filtered_query::<BlockCounterpartyDbObj>(&GET_COUNTERPARTIES_SQL, address, filter)
+        .fetch_all(conn)
+        .await?;
+    Ok(res)
+}
+
+pub async fn insert_block_counterparty<'c, E>(
+    conn: E,
+    counterparty: &BlockCounterpartyDbObj,
+) -> Result<BlockCounterpartyDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_block_counterparty");
+    let res = sqlx::query_as::<_, BlockCounterpartyDbObj>(
+        r"INSERT INTO block_counterparty
+(address, block_number, counterparty, amount_in, amount_out)
+VALUES ($1, $2, $3, $4, $5) RETURNING *;
+",
+    )
+    .bind(&counterparty.address)
+    .bind(counterparty.block_number)
+    .bind(&counterparty.counterparty)
+    .bind(&counterparty.amount_in)
+    .bind(&counterparty.amount_out)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn insert_tx<'c, E>(conn: E, tx: &TxDbObj) -> Result<TxDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_tx");
     let res = sqlx::query_as::<_, TxDbObj>(
         r"INSERT INTO tx
 (address, tx_hash, block_number, block_index, gas_used)
@@ -127,10 +659,14 @@ VALUES ($1, $2, $3, $4, $5) RETURNING *;
     Ok(res)
 }
 
-pub async fn insert_tx_trace(
-    conn: &SqlitePool,
+pub async fn insert_tx_trace<'c, E>(
+    conn: E,
     trace: &TxTraceDbObj,
-) -> Result<TxTraceDbObj, sqlx::Error> {
+) -> Result<TxTraceDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_tx_trace");
     let res = sqlx::query_as::<_, TxTraceDbObj>(
         r"INSERT INTO tx_trace
 (address, tx_hash, block_number, block_index, trace_index, from_addr, to_addr, value, gas_used)
@@ -150,3 +686,459 @@ VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *;
     .await?;
     Ok(res)
 }
+
+pub async fn get_failed_block<'c, E>(
+    conn: E,
+    address: &str,
+    block_number: i64,
+) -> Result<Option<FailedBlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_failed_block");
+    let res = sqlx::query_as::<_, FailedBlockDbObj>(
+        r"SELECT * FROM failed_block WHERE address = $1 AND block_number = $2;",
+    )
+    .bind(address)
+    .bind(block_number)
+    .fetch_optional(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn get_failed_blocks<'c, E>(
+    conn: E,
+    address: &str,
+) -> Result<Vec<FailedBlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_failed_blocks");
+    let res = sqlx::query_as::<_, FailedBlockDbObj>(
+        r"SELECT * FROM failed_block WHERE address = $1 ORDER BY block_number;",
+    )
+    .bind(address)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn get_due_failed_blocks<'c, E>(
+    conn: E,
+    address: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<FailedBlockDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_due_failed_blocks");
+    let res = sqlx::query_as::<_, FailedBlockDbObj>(
+        r"SELECT * FROM failed_block WHERE address = $1 AND next_retry <= $2 ORDER BY block_number;",
+    )
+    .bind(address)
+    .bind(now)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn upsert_failed_block<'c, E>(
+    conn: E,
+    failed_block: &FailedBlockDbObj,
+) -> Result<FailedBlockDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("upsert_failed_block");
+    let res = sqlx::query_as::<_, FailedBlockDbObj>(
+        r"INSERT INTO failed_block
+(address, block_number, error, attempts, first_failed, last_attempt, next_retry)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (address, block_number) DO UPDATE SET
+error = excluded.error,
+attempts = excluded.attempts,
+last_attempt = excluded.last_attempt,
+next_retry = excluded.next_retry
+RETURNING *;
+",
+    )
+    .bind(&failed_block.address)
+    .bind(failed_block.block_number)
+    .bind(&failed_block.error)
+    .bind(failed_block.attempts)
+    .bind(failed_block.first_failed)
+    .bind(failed_block.last_attempt)
+    .bind(failed_block.next_retry)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn delete_failed_block<'c, E>(
+    conn: E,
+    address: &str,
+    block_number: i64,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_failed_block");
+    let _res = sqlx::query(r"DELETE FROM failed_block WHERE address = $1 AND block_number = $2;")
+        .bind(address)
+        .bind(block_number)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_coverage_ranges<'c, E>(
+    conn: E,
+    address: &str,
+) -> Result<Vec<CoverageRangeDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_coverage_ranges");
+    let res = sqlx::query_as::<_, CoverageRangeDbObj>(
+        r"SELECT * FROM scan_coverage WHERE address = $1 ORDER BY block_start;",
+    )
+    .bind(address)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Ranges overlapping or adjacent to [block_start, block_end)
+pub async fn get_coverage_ranges_touching<'c, E>(
+    conn: E,
+    address: &str,
+    block_start: i64,
+    block_end: i64,
+) -> Result<Vec<CoverageRangeDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_coverage_ranges_touching");
+    let res = sqlx::query_as::<_, CoverageRangeDbObj>(
+        r"SELECT * FROM scan_coverage WHERE address = $1 AND block_start <= $3 AND block_end >= $2 ORDER BY block_start;",
+    )
+    .bind(address)
+    .bind(block_start)
+    .bind(block_end)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn insert_coverage_range<'c, E>(
+    conn: E,
+    range: &CoverageRangeDbObj,
+) -> Result<CoverageRangeDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_coverage_range");
+    let res = sqlx::query_as::<_, CoverageRangeDbObj>(
+        r"INSERT INTO scan_coverage
+(address, block_start, block_end, status, updated)
+VALUES ($1, $2, $3, $4, $5) RETURNING *;
+",
+    )
+    .bind(&range.address)
+    .bind(range.block_start)
+    .bind(range.block_end)
+    .bind(&range.status)
+    .bind(range.updated)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn delete_coverage_range<'c, E>(
+    conn: E,
+    address: &str,
+    block_start: i64,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_coverage_range");
+    let _res = sqlx::query(r"DELETE FROM scan_coverage WHERE address = $1 AND block_start = $2;")
+        .bind(address)
+        .bind(block_start)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn block_transaction_test() -> sqlx::Result<()> {
+    use crate::create_sqlite_connection;
+    let conn = create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+
+    let now = chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp(), 0).unwrap();
+    let scan = insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+            first_block_number: 100,
+            first_block_timestamp: now,
+            next_block_number: 100,
+            next_block_timestamp: now,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await?;
+    let block = BlockDbObj {
+        address: scan.address.clone(),
+        block_number: 100,
+        timestamp: now,
+        balance: "1000".to_string(),
+        balance_diff: "10".to_string(),
+        updated: now,
+        block_miner: "0x0000000000000000000000000000000000000002".to_string(),
+        consensus_reward: "10".to_string(),
+        mev_reward: "0".to_string(),
+        block_reward: "0".to_string(),
+        amount_incoming: "0".to_string(),
+        amount_outgoing: "0".to_string(),
+        trace_mode: "trace_block".to_string(),
+        unexplained_value: None,
+        reconciled: true,
+    };
+    let mut next_scan = scan.clone();
+    next_scan.next_block_number = 101;
+
+    // rolled back transaction leaves neither block nor pointer behind
+    let mut db_tx = conn.begin().await?;
+    insert_block(&mut *db_tx, &block).await?;
+    update_scan(&mut *db_tx, &next_scan).await?;
+    db_tx.rollback().await?;
+    assert!(get_blocks(&conn, &scan.address).await?.is_empty());
+    assert_eq!(get_scan(&conn, &scan.address).await?, Some(scan.clone()));
+
+    let mut db_tx = conn.begin().await?;
+    insert_block(&mut *db_tx, &block).await?;
+    update_scan(&mut *db_tx, &next_scan).await?;
+    db_tx.commit().await?;
+    assert_eq!(get_blocks(&conn, &scan.address).await?, vec![block]);
+    assert_eq!(get_scan(&conn, &scan.address).await?, Some(next_scan));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_page_test() -> sqlx::Result<()> {
+    use crate::create_sqlite_connection;
+    let conn = create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+
+    let now = chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp(), 0).unwrap();
+    let address = "0x0000000000000000000000000000000000000001".to_string();
+    insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: address.clone(),
+            first_block_number: 100,
+            first_block_timestamp: now,
+            next_block_number: 110,
+            next_block_timestamp: now,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await?;
+    // equal diffs make sure ties are paged by block number
+    for (block_number, diff, mev) in [
+        (100, 5, 0),
+        (101, 7, 7),
+        (102, 5, 0),
+        (103, 9, 0),
+        (104, 5, 5),
+    ] {
+        insert_block(
+            &conn,
+            &BlockDbObj {
+                balance_diff: diff.to_string(),
+                consensus_reward: (diff - mev).to_string(),
+                mev_reward: mev.to_string(),
+                ..BlockDbObj::test_block(&address, block_number, "2024-06-01T00:00:00Z")
+            },
+        )
+        .await?;
+    }
+
+    let mut query = BlocksPageQuery {
+        sort: BlockSort::BalanceDiff,
+        descending: true,
+        limit: 2,
+        ..Default::default()
+    };
+    let mut numbers = Vec::new();
+    loop {
+        let (blocks, cursor) = get_blocks_page(&conn, &address, &query).await?;
+        numbers.extend(blocks.iter().map(|block| block.block_number));
+        match cursor {
+            Some(cursor) => query.cursor = Some(cursor.to_string().parse().unwrap()),
+            None => break,
+        }
+    }
+    assert_eq!(numbers, vec![103, 101, 104, 102, 100]);
+
+    let query = BlocksPageQuery {
+        activities: vec![BlockActivity::Mev],
+        min_amount: Some(6),
+        limit: 10,
+        ..Default::default()
+    };
+    let (blocks, cursor) = get_blocks_page(&conn, &address, &query).await?;
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].block_number, 101);
+    assert_eq!(cursor, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_page_exact_amounts_test() -> sqlx::Result<()> {
+    use crate::create_sqlite_connection;
+    let conn = create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+
+    let now = chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp(), 0).unwrap();
+    let address = "0x0000000000000000000000000000000000000001".to_string();
+    insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: address.clone(),
+            first_block_number: 100,
+            first_block_timestamp: now,
+            next_block_number: 110,
+            next_block_timestamp: now,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await?;
+    // amounts differing only in the last wei are equal as floats
+    let amount: u128 = 1_000_000_000_000_000_000_000_001;
+    assert_eq!(amount as f64, (amount + 1) as f64);
+    for (block_number, diff) in [
+        (100, amount as i128 + 1),
+        (101, amount as i128),
+        (102, -(amount as i128)),
+        (103, -(amount as i128) - 1),
+        (104, 0),
+    ] {
+        insert_block(
+            &conn,
+            &BlockDbObj {
+                balance_diff: diff.to_string(),
+                amount_incoming: diff.max(0).to_string(),
+                ..BlockDbObj::test_block(&address, block_number, "2024-06-01T00:00:00Z")
+            },
+        )
+        .await?;
+    }
+
+    let mut query = BlocksPageQuery {
+        sort: BlockSort::BalanceDiff,
+        limit: 1,
+        ..Default::default()
+    };
+    let mut numbers = Vec::new();
+    loop {
+        let (blocks, cursor) = get_blocks_page(&conn, &address, &query).await?;
+        numbers.extend(blocks.iter().map(|block| block.block_number));
+        match cursor {
+            Some(cursor) => query.cursor = Some(cursor.to_string().parse().unwrap()),
+            None => break,
+        }
+    }
+    assert_eq!(numbers, vec![103, 102, 104, 101, 100]);
+
+    let query = BlocksPageQuery {
+        min_amount: Some(amount + 1),
+        limit: 10,
+        ..Default::default()
+    };
+    let (blocks, _) = get_blocks_page(&conn, &address, &query).await?;
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].block_number, 100);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn txs_page_test() -> sqlx::Result<()> {
+    use crate::create_sqlite_connection;
+    let conn = create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+
+    let address = "0x0000000000000000000000000000000000000001";
+    let now = chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp(), 0).unwrap();
+    insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: address.to_string(),
+            first_block_number: 100,
+            first_block_timestamp: now,
+            next_block_number: 102,
+            next_block_timestamp: now,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await?;
+    for block_number in [100, 101] {
+        insert_block(
+            &conn,
+            &BlockDbObj::test_block(address, block_number, "2024-06-01T00:00:00Z"),
+        )
+        .await?;
+        for block_index in 0..3 {
+            insert_tx(
+                &conn,
+                &TxDbObj {
+                    address: address.to_string(),
+                    tx_hash: format!("0x{block_number}{block_index}"),
+                    block_number,
+                    block_index,
+                    gas_used: "21000".to_string(),
+                },
+            )
+            .await?;
+        }
+    }
+
+    let mut cursor = None;
+    let mut pages = Vec::new();
+    loop {
+        let (txs, next_cursor) =
+            get_txs(&conn, address, &BlockFilter::default(), cursor, 4).await?;
+        pages.push(txs.iter().map(|tx| tx.tx_hash.clone()).collect::<Vec<_>>());
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor.to_string().parse().unwrap()),
+            None => break,
+        }
+    }
+    assert_eq!(
+        pages,
+        vec![
+            vec!["0x1000", "0x1001", "0x1002", "0x1010"],
+            vec!["0x1011", "0x1012"]
+        ]
+    );
+
+    Ok(())
+}
@@ -1,8 +1,10 @@
 use crate::db::model::UserDbObj;
+use crate::metrics::db_query_timer;
 use sqlx::SqlitePool;
 
 #[allow(dead_code)]
 pub async fn insert_user(conn: &SqlitePool, user: &UserDbObj) -> Result<UserDbObj, sqlx::Error> {
+    let _timer = db_query_timer("insert_user");
     let res = sqlx::query_as::<_, UserDbObj>(
         r"INSERT INTO users
 (uid, email, pass_hash, created_date, last_pass_change)
@@ -24,6 +26,7 @@ pub async fn update_user_password(
     email: &str,
     new_pass_hash: &str,
 ) -> Result<(), sqlx::Error> {
+    let _timer = db_query_timer("update_user_password");
     let _res = sqlx::query(r"UPDATE users SET pass_hash = $1 WHERE email = $2")
         .bind(new_pass_hash)
         .bind(email)
@@ -34,6 +37,7 @@ pub async fn update_user_password(
 
 #[allow(dead_code)]
 pub async fn update_user(conn: &SqlitePool, user: &UserDbObj) -> Result<UserDbObj, sqlx::Error> {
+    let _timer = db_query_timer("update_user");
     let _res = sqlx::query(
         r"UPDATE users SET
 uid = $1,
@@ -59,6 +63,7 @@ WHERE id = $1
 }
 
 pub async fn get_user(conn: &SqlitePool, email: &str) -> Result<UserDbObj, sqlx::Error> {
+    let _timer = db_query_timer("get_user");
     let res = sqlx::query_as::<_, UserDbObj>(r"SELECT * FROM users WHERE email = $1")
         .bind(email)
         .fetch_one(conn)
@@ -0,0 +1,171 @@
+use crate::db::model::webhook::{WebhookDbObj, WebhookDeliveryDbObj};
+use crate::metrics::db_query_timer;
+use sqlx::{Executor, Sqlite};
+
+pub async fn get_webhooks<'c, E>(conn: E, address: &str) -> Result<Vec<WebhookDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_webhooks");
+    let res = sqlx::query_as::<_, WebhookDbObj>(
+        r"SELECT * FROM webhook WHERE address = $1 ORDER BY created;",
+    )
+    .bind(address)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+pub async fn get_webhook<'c, E>(conn: E, id: &str) -> Result<Option<WebhookDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_webhook");
+    let res = sqlx::query_as::<_, WebhookDbObj>(r"SELECT * FROM webhook WHERE id = $1;")
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    Ok(res)
+}
+
+pub async fn insert_webhook<'c, E>(
+    conn: E,
+    webhook: &WebhookDbObj,
+) -> Result<WebhookDbObj, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_webhook");
+    let res = sqlx::query_as::<_, WebhookDbObj>(
+        r"INSERT INTO webhook
+(id, address, url, secret, events, min_transfer, min_reward, created)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *;
+",
+    )
+    .bind(&webhook.id)
+    .bind(&webhook.address)
+    .bind(&webhook.url)
+    .bind(&webhook.secret)
+    .bind(&webhook.events)
+    .bind(&webhook.min_transfer)
+    .bind(&webhook.min_reward)
+    .bind(webhook.created)
+    .fetch_one(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Returns false when there was no such webhook of the address
+pub async fn delete_webhook<'c, E>(conn: E, address: &str, id: &str) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("delete_webhook");
+    let res = sqlx::query(r"DELETE FROM webhook WHERE address = $1 AND id = $2;")
+        .bind(address)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Delivery with the same id already queued is kept as it is
+pub async fn insert_webhook_delivery<'c, E>(
+    conn: E,
+    delivery: &WebhookDeliveryDbObj,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("insert_webhook_delivery");
+    sqlx::query(
+        r"INSERT OR IGNORE INTO webhook_delivery
+(id, webhook_id, event, payload, status, attempts, last_error, next_attempt, created)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+",
+    )
+    .bind(&delivery.id)
+    .bind(&delivery.webhook_id)
+    .bind(&delivery.event)
+    .bind(&delivery.payload)
+    .bind(&delivery.status)
+    .bind(delivery.attempts)
+    .bind(&delivery.last_error)
+    .bind(delivery.next_attempt)
+    .bind(delivery.created)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn update_webhook_delivery<'c, E>(
+    conn: E,
+    delivery: &WebhookDeliveryDbObj,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("update_webhook_delivery");
+    sqlx::query(
+        r"UPDATE webhook_delivery
+SET status = $2, attempts = $3, last_error = $4, next_attempt = $5
+WHERE id = $1;
+",
+    )
+    .bind(&delivery.id)
+    .bind(&delivery.status)
+    .bind(delivery.attempts)
+    .bind(&delivery.last_error)
+    .bind(delivery.next_attempt)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Pending deliveries whose time has come, oldest first
+pub async fn get_due_webhook_deliveries<'c, E>(
+    conn: E,
+    now: chrono::DateTime<chrono::Utc>,
+    limit: i64,
+) -> Result<Vec<WebhookDeliveryDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_due_webhook_deliveries");
+    let res = sqlx::query_as::<_, WebhookDeliveryDbObj>(
+        r"SELECT * FROM webhook_delivery
+WHERE status = 'pending' AND next_attempt <= $1
+ORDER BY next_attempt
+LIMIT $2;
+",
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
+
+/// Most recent deliveries of the webhook first
+pub async fn get_webhook_deliveries<'c, E>(
+    conn: E,
+    webhook_id: &str,
+    limit: i64,
+) -> Result<Vec<WebhookDeliveryDbObj>, sqlx::Error>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let _timer = db_query_timer("get_webhook_deliveries");
+    let res = sqlx::query_as::<_, WebhookDeliveryDbObj>(
+        r"SELECT * FROM webhook_delivery
+WHERE webhook_id = $1
+ORDER BY created DESC
+LIMIT $2;
+",
+    )
+    .bind(webhook_id)
+    .bind(limit)
+    .fetch_all(conn)
+    .await?;
+    Ok(res)
+}
@@ -12,6 +12,18 @@ pub struct WebPortalError {
     pub column: u32,
 }
 
+impl WebPortalError {
+    /// Prepend message to the error, keeping inner error so callers can still match on it
+    pub fn with_msg(mut self, msg: impl Into<String>) -> Self {
+        let msg = msg.into();
+        self.msg = Some(match self.msg.take() {
+            Some(prev) => format!("{}: {}", msg, prev),
+            None => msg,
+        });
+        self
+    }
+}
+
 impl Error for WebPortalError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         Some(&self.inner)
@@ -51,3 +63,17 @@ impl std::fmt::Display for WebPortalError {
         }
     }
 }
+
+#[test]
+fn with_msg_keeps_inner_test() {
+    use crate::err_create;
+
+    let err = err_create!(std::time::Duration::from_secs(30))
+        .with_msg("Error getting block 5")
+        .with_msg("Error inspecting block");
+    assert!(matches!(err.inner, ErrorBag::TimeLimitReached(_)));
+    assert_eq!(
+        err.msg.as_deref(),
+        Some("Error inspecting block: Error getting block 5")
+    );
+}
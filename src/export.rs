@@ -0,0 +1,331 @@
+use crate::db::ops::transaction::{
+    stream_blocks, stream_tx_traces, stream_txs, stream_withdrawal_blocks, BlockFilter,
+};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::price::parse_timestamp;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use web3::types::Address;
+
+/// Encoded rows are buffered into chunks of about that size
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks waiting for the consumer, stops reading rows when the client is slow
+const EXPORT_CHANNEL_CAPACITY: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportKind {
+    Blocks,
+    Txs,
+    Traces,
+    Withdrawals,
+}
+
+impl Display for ExportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportKind::Blocks => write!(f, "blocks"),
+            ExportKind::Txs => write!(f, "txs"),
+            ExportKind::Traces => write!(f, "traces"),
+            ExportKind::Withdrawals => write!(f, "withdrawals"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// Consensus layer withdrawal credited in a block
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WithdrawalRow {
+    address: String,
+    block_number: i64,
+    timestamp: DateTime<Utc>,
+    amount: String,
+}
+
+fn encode_row<T: Serialize>(
+    row: &T,
+    format: ExportFormat,
+    with_header: bool,
+    out: &mut Vec<u8>,
+) -> Result<(), WebPortalError> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(with_header)
+                .from_writer(out);
+            writer
+                .serialize(row)
+                .and_then(|_| writer.flush().map_err(csv::Error::from))
+                .map_err(|e| err_custom_create!("Error encoding CSV row: {}", e))
+        }
+        ExportFormat::Ndjson => {
+            serde_json::to_writer(&mut *out, row)
+                .map_err(|e| err_custom_create!("Error encoding JSON row: {}", e))?;
+            out.push(b'\n');
+            Ok(())
+        }
+    }
+}
+
+/// Encode rows into chunks, returns false when the receiver is gone
+async fn send_rows<T, S>(
+    rows: S,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Vec<u8>, WebPortalError>>,
+) -> Result<bool, WebPortalError>
+where
+    T: Serialize,
+    S: Stream<Item = Result<T, sqlx::Error>>,
+{
+    let mut rows = std::pin::pin!(rows);
+    let mut chunk = Vec::new();
+    let mut first = true;
+    while let Some(row) = rows.next().await {
+        let row = row.map_err(|e| err_custom_create!("Error reading rows: {}", e))?;
+        encode_row(&row, format, first, &mut chunk)?;
+        first = false;
+        if chunk.len() >= EXPORT_CHUNK_SIZE
+            && sender.send(Ok(std::mem::take(&mut chunk))).await.is_err()
+        {
+            return Ok(false);
+        }
+    }
+    Ok(chunk.is_empty() || sender.send(Ok(chunk)).await.is_ok())
+}
+
+/// Export rows in background task. Chunks are read from the receiver,
+/// an error ends the export.
+pub fn spawn_export(
+    conn: SqlitePool,
+    address: String,
+    kind: ExportKind,
+    format: ExportFormat,
+    filter: BlockFilter,
+) -> mpsc::Receiver<Result<Vec<u8>, WebPortalError>> {
+    let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let res = match kind {
+            ExportKind::Blocks => {
+                send_rows(stream_blocks(&conn, &address, &filter), format, &sender).await
+            }
+            ExportKind::Txs => {
+                send_rows(stream_txs(&conn, &address, &filter), format, &sender).await
+            }
+            ExportKind::Traces => {
+                send_rows(stream_tx_traces(&conn, &address, &filter), format, &sender).await
+            }
+            ExportKind::Withdrawals => {
+                let rows = stream_withdrawal_blocks(&conn, &address, &filter).map(|block| {
+                    block.map(|block| WithdrawalRow {
+                        address: block.address,
+                        block_number: block.block_number,
+                        timestamp: block.timestamp,
+                        amount: block.consensus_reward,
+                    })
+                });
+                send_rows(rows, format, &sender).await
+            }
+        };
+        match res {
+            Ok(true) => {}
+            Ok(false) => log::warn!("Export of {} cancelled by receiver", address),
+            Err(e) => {
+                log::error!("Error exporting {}: {}", address, e);
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+    });
+    receiver
+}
+
+/// Parse `from`/`to` filter values, accepts the same formats as price import
+pub fn parse_filter_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_timestamp(value).ok_or(format!("Invalid timestamp {}", value))
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ExportCommand {
+    #[arg(long)]
+    address: Address,
+    #[arg(long, value_enum)]
+    kind: ExportKind,
+    #[arg(long, value_enum, default_value = "csv")]
+    format: ExportFormat,
+    #[arg(long)]
+    block_start: Option<i64>,
+    /// Exclusive
+    #[arg(long)]
+    block_end: Option<i64>,
+    /// Date, RFC 3339 time or unix timestamp
+    #[arg(long, value_parser = parse_filter_timestamp)]
+    from: Option<DateTime<Utc>>,
+    /// Exclusive, same formats as --from
+    #[arg(long, value_parser = parse_filter_timestamp)]
+    to: Option<DateTime<Utc>>,
+    /// Written to standard output when not given
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+pub async fn export_command(
+    conn: SqlitePool,
+    export_command: ExportCommand,
+) -> Result<(), WebPortalError> {
+    let ExportCommand {
+        address,
+        kind,
+        format,
+        block_start,
+        block_end,
+        from,
+        to,
+        output,
+    } = export_command;
+
+    let mut out: Box<dyn Write> = match &output {
+        Some(path) => Box::new(std::io::BufWriter::new(
+            std::fs::File::create(path)
+                .map_err(|e| err_custom_create!("Error creating {}: {}", path.display(), e))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let filter = BlockFilter {
+        block_start,
+        block_end,
+        from,
+        to,
+    };
+    let mut receiver = spawn_export(conn, format!("{address:#x}"), kind, format, filter);
+    while let Some(chunk) = receiver.recv().await {
+        out.write_all(&chunk?)
+            .map_err(|e| err_custom_create!("Error writing export: {}", e))?;
+    }
+    out.flush()
+        .map_err(|e| err_custom_create!("Error writing export: {}", e))?;
+    Ok(())
+}
+
+#[test]
+fn encode_row_test() {
+    let row = WithdrawalRow {
+        address: "0x01".to_string(),
+        block_number: 5,
+        timestamp: DateTime::from_timestamp(1717200000, 0).unwrap(),
+        amount: "100".to_string(),
+    };
+    let mut out = Vec::new();
+    encode_row(&row, ExportFormat::Csv, true, &mut out).unwrap();
+    encode_row(&row, ExportFormat::Csv, false, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "address,blockNumber,timestamp,amount\n\
+         0x01,5,2024-06-01T00:00:00Z,100\n\
+         0x01,5,2024-06-01T00:00:00Z,100\n"
+    );
+
+    let mut out = Vec::new();
+    encode_row(&row, ExportFormat::Ndjson, true, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"address\":\"0x01\",\"blockNumber\":5,\"timestamp\":\"2024-06-01T00:00:00Z\",\"amount\":\"100\"}\n"
+    );
+}
+
+#[tokio::test]
+async fn export_blocks_test() -> Result<(), WebPortalError> {
+    use crate::create_sqlite_connection;
+    use crate::db::model::transaction::{BlockDbObj, ScanDbObj};
+    use crate::db::ops::transaction::{insert_block, insert_scan};
+
+    let conn = create_sqlite_connection(None, None, false, true).await?;
+    let address = "0x0000000000000000000000000000000000000001";
+    let block = |block_number: i64, timestamp| BlockDbObj {
+        consensus_reward: block_number.to_string(),
+        ..BlockDbObj::test_block(address, block_number, timestamp)
+    };
+    let blocks = [
+        block(100, "2024-05-31T12:00:00Z"),
+        block(101, "2024-06-01T12:00:00Z"),
+        block(102, "2024-06-02T12:00:00Z"),
+        block(103, "2024-06-03T12:00:00Z"),
+    ];
+    insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: address.to_string(),
+            first_block_number: 100,
+            first_block_timestamp: blocks[0].timestamp,
+            next_block_number: 104,
+            next_block_timestamp: blocks[3].timestamp,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await
+    .map_err(|e| err_custom_create!("Error inserting scan: {}", e))?;
+    for block in &blocks {
+        insert_block(&conn, block)
+            .await
+            .map_err(|e| err_custom_create!("Error inserting block: {}", e))?;
+    }
+
+    let filter = BlockFilter {
+        block_start: Some(101),
+        block_end: None,
+        from: None,
+        to: Some(parse_filter_timestamp("2024-06-03").unwrap()),
+    };
+    let mut receiver = spawn_export(
+        conn,
+        address.to_string(),
+        ExportKind::Withdrawals,
+        ExportFormat::Csv,
+        filter,
+    );
+    let mut out = Vec::new();
+    while let Some(chunk) = receiver.recv().await {
+        out.extend(chunk?);
+    }
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+            "address,blockNumber,timestamp,amount\n\
+             {address},101,2024-06-01T12:00:00Z,101\n\
+             {address},102,2024-06-02T12:00:00Z,102\n"
+        )
+    );
+    Ok(())
+}
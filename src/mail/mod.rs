@@ -0,0 +1,547 @@
+use crate::api::user::WEB_PORTAL_DOMAIN;
+use crate::db::model::mail::MailRecipientDbObj;
+use crate::db::model::transaction::{format_eth, wei, BlockDbObj, FailedBlockDbObj, ScanDbObj};
+use crate::db::ops::mail::{
+    get_blocks_failed_since, get_blocks_updated_since, get_mail_recipients, insert_mail_sent,
+    is_mail_sent,
+};
+use crate::db::ops::transaction::{get_all_scans, stream_blocks, BlockFilter};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::metrics;
+use crate::scan::labels::get_label_map;
+use chrono::{DateTime, Datelike, Days, Utc};
+use clap::Parser;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::env;
+use std::time::Duration;
+
+/// How often alerts and digests are checked for
+const CHECK_INTERVAL: Duration = Duration::from_secs(300);
+/// Events older than that are not alerted about, even when the mailer was not running
+const ALERT_LOOKBACK_HOURS: i64 = 24;
+/// Alerts listed in one mail, the rest is only counted
+const MAX_ALERTS_PER_MAIL: usize = 50;
+
+lazy_static! {
+    /// Mail is not sent when not set
+    static ref SMTP_HOST: Option<String> = env::var("SMTP_HOST").ok().filter(|host| !host.is_empty());
+    /// Default port of SMTP_TLS mode when not set
+    static ref SMTP_PORT: Option<u16> = env::var("SMTP_PORT").ok().and_then(|port| port.parse().ok());
+    static ref SMTP_USERNAME: Option<String> = env::var("SMTP_USERNAME").ok();
+    static ref SMTP_PASSWORD: Option<String> = env::var("SMTP_PASSWORD").ok();
+    /// starttls, tls or none (plain connection, for a local SMTP sink)
+    static ref SMTP_TLS: String = env::var("SMTP_TLS").unwrap_or("starttls".to_string());
+    static ref MAIL_FROM: String =
+        env::var("MAIL_FROM").unwrap_or(format!("web-portal@{}", *WEB_PORTAL_DOMAIN));
+    /// Scan is reported as stalled when its next block is older than that
+    static ref SCAN_STALLED_AFTER_MINUTES: i64 = env::var("SCAN_STALLED_AFTER_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(60);
+}
+
+pub type Mailer = AsyncSmtpTransport<Tokio1Executor>;
+
+/// SMTP transport configured from environment, None when SMTP_HOST is not set
+pub fn create_mailer() -> Result<Option<Mailer>, WebPortalError> {
+    let Some(host) = SMTP_HOST.as_deref() else {
+        return Ok(None);
+    };
+    let builder = match SMTP_TLS.as_str() {
+        "none" => Mailer::builder_dangerous(host),
+        "starttls" => Mailer::starttls_relay(host)
+            .map_err(|e| err_custom_create!("Error creating SMTP transport: {}", e))?,
+        "tls" => Mailer::relay(host)
+            .map_err(|e| err_custom_create!("Error creating SMTP transport: {}", e))?,
+        other => return Err(err_custom_create!("Unknown SMTP_TLS mode {}", other)),
+    };
+    let builder = match *SMTP_PORT {
+        Some(port) => builder.port(port),
+        None => builder,
+    };
+    let builder = match (SMTP_USERNAME.as_ref(), SMTP_PASSWORD.as_ref()) {
+        (Some(username), Some(password)) => {
+            builder.credentials(Credentials::new(username.clone(), password.clone()))
+        }
+        _ => builder,
+    };
+    Ok(Some(builder.build()))
+}
+
+async fn send_mail(
+    mailer: &Mailer,
+    kind: &str,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<(), WebPortalError> {
+    let message = Message::builder()
+        .from(
+            MAIL_FROM
+                .parse()
+                .map_err(|e| err_custom_create!("Invalid MAIL_FROM {}: {}", *MAIL_FROM, e))?,
+        )
+        .to(to
+            .parse()
+            .map_err(|e| err_custom_create!("Invalid recipient {}: {}", to, e))?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)
+        .map_err(|e| err_custom_create!("Error building mail: {}", e))?;
+    mailer
+        .send(message)
+        .await
+        .map_err(|e| err_custom_create!("Error sending mail to {}: {}", to, e))?;
+    metrics::inc_mail_sent(kind);
+    log::info!("Sent {} mail to {}", kind, to);
+    Ok(())
+}
+
+fn render(name: &str, context: minijinja::Value) -> Result<String, WebPortalError> {
+    let mut env = minijinja::Environment::new();
+    env.add_template("alerts", include_str!("templates/alerts.txt"))
+        .and_then(|_| env.add_template("digest", include_str!("templates/digest.txt")))
+        .map_err(|e| err_custom_create!("Invalid mail template: {}", e))?;
+    env.get_template(name)
+        .and_then(|template| template.render(context))
+        .map_err(|e| err_custom_create!("Error rendering mail {}: {}", name, e))
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct MailAlert {
+    /// Saved in mail_sent, so every event is mailed once
+    topic: String,
+    title: &'static str,
+    address: String,
+    label: Option<String>,
+    block_number: Option<i64>,
+    detail: String,
+}
+
+/// Alerts about large incoming transfers, unreconciled and failed blocks and scans not moving forward
+fn collect_alerts(
+    min_transfer: &str,
+    blocks: &[BlockDbObj],
+    failed_blocks: &[FailedBlockDbObj],
+    scans: &[ScanDbObj],
+    now: DateTime<Utc>,
+) -> Vec<MailAlert> {
+    let min_transfer = wei(min_transfer).max(1);
+    let transfers = blocks
+        .iter()
+        .filter(|block| wei(&block.amount_incoming) >= min_transfer)
+        .map(|block| MailAlert {
+            topic: format!("transfer:{}:{}", block.address, block.block_number),
+            title: "Large transfer",
+            address: block.address.clone(),
+            label: None,
+            block_number: Some(block.block_number),
+            detail: format!("Received {} ETH", format_eth(wei(&block.amount_incoming))),
+        });
+    let anomalies = blocks
+        .iter()
+        .filter(|block| !block.reconciled)
+        .map(|block| MailAlert {
+            topic: format!("anomaly:{}:{}", block.address, block.block_number),
+            title: "Anomaly",
+            address: block.address.clone(),
+            label: None,
+            block_number: Some(block.block_number),
+            detail: "Found transfers do not add up to the balance change".to_string(),
+        });
+    let failures = failed_blocks.iter().map(|failed| MailAlert {
+        topic: format!("failed:{}:{}", failed.address, failed.block_number),
+        title: "Inspection failed",
+        address: failed.address.clone(),
+        label: None,
+        block_number: Some(failed.block_number),
+        detail: failed.error.clone(),
+    });
+    // position is part of the topic, so a scan stalled again later is reported again.
+    // Scans not following the chain head stop at their end block on purpose.
+    let stalled = scans
+        .iter()
+        .filter(|scan| {
+            scan.follow
+                && now - scan.next_block_timestamp
+                    > chrono::Duration::minutes(*SCAN_STALLED_AFTER_MINUTES)
+        })
+        .map(|scan| MailAlert {
+            topic: format!("stalled:{}:{}", scan.address, scan.next_block_number),
+            title: "Scan stalled",
+            address: scan.address.clone(),
+            label: None,
+            block_number: None,
+            detail: format!(
+                "Next block {} is from {}",
+                scan.next_block_number,
+                scan.next_block_timestamp.to_rfc3339()
+            ),
+        });
+    transfers
+        .chain(anomalies)
+        .chain(failures)
+        .chain(stalled)
+        .collect()
+}
+
+async fn send_alerts(
+    conn: &SqlitePool,
+    mailer: &Mailer,
+    recipient: &MailRecipientDbObj,
+    now: DateTime<Utc>,
+) -> Result<(), WebPortalError> {
+    let settings = &recipient.settings;
+    let since = settings
+        .alerts_since
+        .max(now - chrono::Duration::hours(ALERT_LOOKBACK_HOURS));
+    let blocks = get_blocks_updated_since(conn, since)
+        .await
+        .map_err(|e| err_custom_create!("Error getting blocks: {}", e))?;
+    let failed_blocks = get_blocks_failed_since(conn, since)
+        .await
+        .map_err(|e| err_custom_create!("Error getting failed blocks: {}", e))?;
+    let scans = get_all_scans(conn)
+        .await
+        .map_err(|e| err_custom_create!("Error getting scans: {}", e))?;
+
+    let mut alerts = Vec::new();
+    for alert in collect_alerts(&settings.min_transfer, &blocks, &failed_blocks, &scans, now) {
+        let sent = is_mail_sent(conn, &settings.uid, &alert.topic)
+            .await
+            .map_err(|e| err_custom_create!("Error getting sent mail: {}", e))?;
+        if !sent {
+            alerts.push(alert);
+        }
+    }
+    if alerts.is_empty() {
+        return Ok(());
+    }
+    let labels = get_label_map(conn).await?;
+    for alert in &mut alerts {
+        alert.label = labels.get(&alert.address).map(|label| label.name.clone());
+    }
+
+    let body = render(
+        "alerts",
+        minijinja::context! {
+            domain => *WEB_PORTAL_DOMAIN,
+            alerts => alerts.iter().take(MAX_ALERTS_PER_MAIL).collect::<Vec<_>>(),
+            more => alerts.len().saturating_sub(MAX_ALERTS_PER_MAIL),
+        },
+    )?;
+    let subject = match alerts.as_slice() {
+        [alert] => format!("{}: {}", alert.title, alert.address),
+        alerts => format!("{} new events", alerts.len()),
+    };
+    send_mail(mailer, "alerts", &recipient.email, &subject, body).await?;
+    for alert in alerts {
+        insert_mail_sent(conn, &settings.uid, &alert.topic, now)
+            .await
+            .map_err(|e| err_custom_create!("Error saving sent mail: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Income of one address in the digest, amounts in ETH
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct AddressIncome {
+    address: String,
+    label: Option<String>,
+    blocks: usize,
+    consensus_reward: String,
+    mev_reward: String,
+    block_reward: String,
+    amount_incoming: String,
+    amount_outgoing: String,
+}
+
+fn sum_income(address: &str, blocks: &[BlockDbObj]) -> AddressIncome {
+    let sum = |amount: fn(&BlockDbObj) -> &str| {
+        format_eth(blocks.iter().map(|block| wei(amount(block))).sum())
+    };
+    AddressIncome {
+        address: address.to_string(),
+        label: None,
+        blocks: blocks.len(),
+        consensus_reward: sum(|block| &block.consensus_reward),
+        mev_reward: sum(|block| &block.mev_reward),
+        block_reward: sum(|block| &block.block_reward),
+        amount_incoming: sum(|block| &block.amount_incoming),
+        amount_outgoing: sum(|block| &block.amount_outgoing),
+    }
+}
+
+/// Last full week (Monday to Monday in UTC) before now and its ISO name, like 2024-W42
+fn last_week(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>, String) {
+    let today = now.date_naive();
+    let week_end = today - Days::new(today.weekday().num_days_from_monday() as u64);
+    let week_start = week_end - Days::new(7);
+    let week = week_start.iso_week();
+    (
+        week_start
+            .and_hms_opt(0, 0, 0)
+            .unwrap_or_default()
+            .and_utc(),
+        week_end.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+        format!("{}-W{:02}", week.year(), week.week()),
+    )
+}
+
+/// Digest mail of the last week, with topic to save it under
+async fn render_digest(
+    conn: &SqlitePool,
+    now: DateTime<Utc>,
+) -> Result<(String, String, String), WebPortalError> {
+    let (from, to, week) = last_week(now);
+    let scans = get_all_scans(conn)
+        .await
+        .map_err(|e| err_custom_create!("Error getting scans: {}", e))?;
+    let labels = get_label_map(conn).await?;
+    let filter = BlockFilter {
+        from: Some(from),
+        to: Some(to),
+        ..Default::default()
+    };
+    let mut addresses = Vec::new();
+    for scan in scans {
+        let mut blocks = Vec::new();
+        let mut stream = stream_blocks(conn, &scan.address, &filter);
+        while let Some(block) = stream.next().await {
+            blocks.push(block.map_err(|e| err_custom_create!("Error reading blocks: {}", e))?);
+        }
+        let mut income = sum_income(&scan.address, &blocks);
+        income.label = labels.get(&scan.address).map(|label| label.name.clone());
+        addresses.push(income);
+    }
+
+    let body = render(
+        "digest",
+        minijinja::context! {
+            domain => *WEB_PORTAL_DOMAIN,
+            week => week,
+            from => from.date_naive().to_string(),
+            to => (to - Days::new(1)).date_naive().to_string(),
+            addresses => addresses,
+        },
+    )?;
+    Ok((
+        format!("Weekly digest {week}"),
+        body,
+        format!("digest:{week}"),
+    ))
+}
+
+/// Name of the last week when its digest is due, weeks ended before opting in are skipped
+fn digest_week(digest_since: DateTime<Utc>, now: DateTime<Utc>) -> Option<String> {
+    let (_, week_end, week) = last_week(now);
+    (week_end > digest_since).then_some(week)
+}
+
+async fn send_digest(
+    conn: &SqlitePool,
+    mailer: &Mailer,
+    recipient: &MailRecipientDbObj,
+    now: DateTime<Utc>,
+) -> Result<(), WebPortalError> {
+    let Some(week) = digest_week(recipient.settings.digest_since, now) else {
+        return Ok(());
+    };
+    let sent = is_mail_sent(conn, &recipient.settings.uid, &format!("digest:{week}"))
+        .await
+        .map_err(|e| err_custom_create!("Error getting sent mail: {}", e))?;
+    if sent {
+        return Ok(());
+    }
+    let (subject, body, topic) = render_digest(conn, now).await?;
+    send_mail(mailer, "digest", &recipient.email, &subject, body).await?;
+    insert_mail_sent(conn, &recipient.settings.uid, &topic, now)
+        .await
+        .map_err(|e| err_custom_create!("Error saving sent mail: {}", e))
+}
+
+async fn send_due_mail(conn: &SqlitePool, mailer: &Mailer) -> Result<(), WebPortalError> {
+    let now = Utc::now();
+    let recipients = get_mail_recipients(conn)
+        .await
+        .map_err(|e| err_custom_create!("Error getting mail recipients: {}", e))?;
+    for recipient in recipients {
+        if recipient.settings.alerts {
+            if let Err(e) = send_alerts(conn, mailer, &recipient, now).await {
+                log::warn!("Error sending alerts to {}: {}", recipient.email, e);
+            }
+        }
+        if recipient.settings.weekly_digest {
+            if let Err(e) = send_digest(conn, mailer, &recipient, now).await {
+                log::warn!("Error sending digest to {}: {}", recipient.email, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send alerts and weekly digests to users who opted in, runs forever.
+/// Mail that failed to send is tried again in the next round.
+pub async fn run_mailer(conn: SqlitePool) {
+    let mailer = match create_mailer() {
+        Ok(Some(mailer)) => mailer,
+        Ok(None) => {
+            log::info!("SMTP_HOST not set, mail is not sent");
+            return;
+        }
+        Err(e) => {
+            log::error!("Mail is not sent: {}", e);
+            return;
+        }
+    };
+    loop {
+        if let Err(e) = send_due_mail(&conn, &mailer).await {
+            log::error!("Error sending mail: {}", e);
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct MailCommand {
+    /// Recipient, does not have to be a portal user
+    #[arg(long)]
+    to: String,
+    /// Send the digest of the last week instead of a test mail
+    #[arg(long)]
+    digest: bool,
+}
+
+/// Check SMTP settings, for example against a local SMTP sink
+pub async fn mail_command(
+    conn: SqlitePool,
+    mail_command: MailCommand,
+) -> Result<(), WebPortalError> {
+    let mailer = create_mailer()?.ok_or(err_custom_create!("SMTP_HOST not set"))?;
+    if mail_command.digest {
+        let (subject, body, _) = render_digest(&conn, Utc::now()).await?;
+        send_mail(&mailer, "digest", &mail_command.to, &subject, body).await
+    } else {
+        let body = render(
+            "alerts",
+            minijinja::context! {
+                domain => *WEB_PORTAL_DOMAIN,
+                alerts => vec![MailAlert {
+                    topic: "test".to_string(),
+                    title: "Test",
+                    address: "0x0000000000000000000000000000000000000000".to_string(),
+                    label: None,
+                    block_number: None,
+                    detail: "Mail settings work".to_string(),
+                }],
+            },
+        )?;
+        send_mail(&mailer, "test", &mail_command.to, "Test mail", body).await
+    }
+}
+
+#[test]
+fn mail_alerts_test() {
+    let block = |block_number, amount_incoming: &str| BlockDbObj {
+        balance_diff: amount_incoming.to_string(),
+        amount_incoming: amount_incoming.to_string(),
+        ..BlockDbObj::test_block("0x01", block_number, "2024-10-23T11:00:00Z")
+    };
+    let now = DateTime::parse_from_rfc3339("2024-10-23T12:00:00Z")
+        .unwrap()
+        .to_utc();
+    let scan = ScanDbObj {
+        address: "0x02".to_string(),
+        first_block_number: 1,
+        first_block_timestamp: now,
+        next_block_number: 100,
+        next_block_timestamp: now - chrono::Duration::hours(5),
+        finality_policy: None,
+        finality_block_number: None,
+        follow: true,
+    };
+    let blocks = [
+        block(1, "1500000000000000000"),
+        block(2, "10"),
+        block(3, "0"),
+    ];
+
+    let alerts = collect_alerts(
+        "1000000000000000000",
+        &blocks,
+        &[],
+        std::slice::from_ref(&scan),
+        now,
+    );
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[0].topic, "transfer:0x01:1");
+    assert_eq!(alerts[0].detail, "Received 1.500000 ETH");
+    assert_eq!(alerts[1].topic, "stalled:0x02:100");
+    assert_eq!(collect_alerts("0", &blocks, &[], &[], now).len(), 2);
+    let unreconciled = BlockDbObj {
+        reconciled: false,
+        ..block(4, "0")
+    };
+    let failed = FailedBlockDbObj {
+        address: "0x01".to_string(),
+        block_number: 5,
+        error: "Timeout".to_string(),
+        attempts: 1,
+        first_failed: now,
+        last_attempt: now,
+        next_retry: now,
+    };
+    let topics: Vec<String> = collect_alerts("0", &[unreconciled], &[failed], &[], now)
+        .into_iter()
+        .map(|alert| alert.topic)
+        .collect();
+    assert_eq!(topics, vec!["anomaly:0x01:4", "failed:0x01:5"]);
+    // scan stopping at its end block is not stalled
+    let finished = ScanDbObj {
+        follow: false,
+        ..scan.clone()
+    };
+    assert!(collect_alerts("0", &[], &[], &[finished], now).is_empty());
+
+    let body = render(
+        "alerts",
+        minijinja::context! { domain => "example.com", alerts => alerts },
+    )
+    .unwrap();
+    assert!(body.contains("there are 2 new events on the addresses scanned by example.com"));
+    assert!(body.contains("- Large transfer: 0x01, block 1\n  Received 1.500000 ETH"));
+    let body = render(
+        "alerts",
+        minijinja::context! { domain => "example.com", alerts => &alerts[..1], more => 3 },
+    )
+    .unwrap();
+    assert!(body.contains("there are 4 new events"));
+    assert!(body.contains("- and 3 more"));
+
+    let income = sum_income("0x01", &blocks);
+    assert_eq!(income.blocks, 3);
+    assert_eq!(income.amount_incoming, "1.500000");
+
+    let (from, to, week) = last_week(now);
+    assert_eq!(from.to_rfc3339(), "2024-10-14T00:00:00+00:00");
+    assert_eq!(to.to_rfc3339(), "2024-10-21T00:00:00+00:00");
+    assert_eq!(week, "2024-W42");
+    // opted in during 2024-W43, first digest is the one of that week
+    let digest_since = DateTime::parse_from_rfc3339("2024-10-22T08:00:00Z")
+        .unwrap()
+        .to_utc();
+    assert_eq!(digest_week(digest_since, now), None);
+    assert_eq!(
+        digest_week(digest_since, now + chrono::Duration::days(7)),
+        Some("2024-W43".to_string())
+    );
+}
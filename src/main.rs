@@ -2,20 +2,30 @@ mod api;
 mod cookie;
 mod db;
 mod error;
+mod export;
+mod mail;
+mod metrics;
+mod price;
+mod report;
 mod scan;
+mod summary;
 mod update;
+mod webhook;
 
+use crate::api::notification;
 use crate::api::user;
 use crate::api::user::{UserSessions, WEB_PORTAL_DOMAIN};
 use crate::cookie::load_key_or_create;
 use crate::db::connection::create_sqlite_connection;
-use crate::scan::api::get_scan_scope;
+use crate::scan::api::{get_labels_scope, get_scan_scope};
+use crate::scan::queue::ScanQueue;
 use actix_multipart::form::MultipartFormConfig;
 use actix_multipart::MultipartError;
 use actix_session::config::CookieContentSecurity;
 use actix_session::storage::CookieSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::SameSite;
+use actix_web::dev::Service;
 use actix_web::{
     web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder, Scope,
 };
@@ -31,6 +41,7 @@ use tokio::sync::Mutex;
 pub struct ServerData {
     pub db_connection: Arc<Mutex<SqlitePool>>,
     pub open_sessions: Arc<Mutex<HashMap<String, UserSessions>>>,
+    pub scan_queue: ScanQueue,
 }
 
 #[cfg(feature = "dashboard")]
@@ -114,7 +125,11 @@ pub async fn dashboard_serve(
     HttpResponse::NotFound().body(format!("404 Not Found: {}", path))
 }
 
-use crate::scan::cmd::scan_command;
+use crate::export::export_command;
+use crate::price::import_prices_command;
+use crate::report::report_command;
+use crate::scan::cmd::{coverage_command, scan_command};
+use crate::scan::demo::demo_command;
 
 /// Enum that defines the available subcommands
 #[derive(Subcommand)]
@@ -124,6 +139,36 @@ enum Commands {
         #[clap(flatten)]
         scan: scan::cmd::ScanCommand,
     },
+    /// Show blocks of the scan that were not inspected
+    Coverage {
+        #[clap(flatten)]
+        coverage: scan::cmd::CoverageCommand,
+    },
+    /// Fill database with synthetic scans, for development without a node
+    Demo {
+        #[clap(flatten)]
+        demo: scan::demo::DemoCommand,
+    },
+    /// Import ETH prices used for fiat valuation of blocks
+    ImportPrices {
+        #[clap(flatten)]
+        import: price::ImportPricesCommand,
+    },
+    /// Yearly income report of an address or address group
+    Report {
+        #[clap(flatten)]
+        report: report::ReportCommand,
+    },
+    /// Export blocks, transactions, traces or withdrawals as CSV or NDJSON
+    Export {
+        #[clap(flatten)]
+        export: export::ExportCommand,
+    },
+    /// Send a test mail or the weekly digest, to check SMTP settings
+    Mail {
+        #[clap(flatten)]
+        mail: mail::MailCommand,
+    },
     /// Start web server
     Server {
         #[arg(long, default_value = "localhost:80")]
@@ -169,15 +214,45 @@ async fn main() -> std::io::Result<()> {
     match args.cmd {
         Commands::Scan { scan } => scan_command(conn, scan).await.map_err(|e| {
             log::error!("Error: {e}");
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Error: {e}"))
+            std::io::Error::other(format!("Error: {e}"))
+        }),
+        Commands::Demo { demo } => demo_command(conn, demo).await.map_err(|e| {
+            log::error!("Error: {e}");
+            std::io::Error::other(format!("Error: {e}"))
+        }),
+        Commands::ImportPrices { import } => {
+            import_prices_command(conn, import).await.map_err(|e| {
+                log::error!("Error: {e}");
+                std::io::Error::other(format!("Error: {e}"))
+            })
+        }
+        Commands::Report { report } => report_command(conn, report).await.map_err(|e| {
+            log::error!("Error: {e}");
+            std::io::Error::other(format!("Error: {e}"))
+        }),
+        Commands::Export { export } => export_command(conn, export).await.map_err(|e| {
+            log::error!("Error: {e}");
+            std::io::Error::other(format!("Error: {e}"))
+        }),
+        Commands::Mail { mail } => mail::mail_command(conn, mail).await.map_err(|e| {
+            log::error!("Error: {e}");
+            std::io::Error::other(format!("Error: {e}"))
+        }),
+        Commands::Coverage { coverage } => coverage_command(conn, coverage).await.map_err(|e| {
+            log::error!("Error: {e}");
+            std::io::Error::other(format!("Error: {e}"))
         }),
         Commands::Server { addr, threads } => {
+            let scan_queue = ScanQueue::start(conn.clone());
+            actix_web::rt::spawn(webhook::run_delivery_worker(conn.clone()));
+            actix_web::rt::spawn(mail::run_mailer(conn.clone()));
             HttpServer::new(move || {
                 let cors = actix_cors::Cors::permissive();
 
                 let server_data = web::Data::new(Box::new(ServerData {
                     db_connection: Arc::new(Mutex::new(conn.clone())),
                     open_sessions: Arc::new(Default::default()),
+                    scan_queue: scan_queue.clone(),
                 }));
                 let client = web::Data::new(Client::new());
                 let session_middleware =
@@ -191,15 +266,45 @@ async fn main() -> std::io::Result<()> {
 
                 let api_scope = Scope::new("/api")
                     .service(get_scan_scope())
+                    .service(get_labels_scope())
+                    .route("/tx/{hash}", web::get().to(scan::api::web_get_tx))
                     .route("/login", web::post().to(user::handle_login))
                     .route("/session/check", web::get().to(user::handle_session_check))
                     .route("/is_login", web::get().to(user::handle_is_login))
                     .route("/is_login", web::post().to(user::handle_is_login))
                     .route("/logout", web::post().to(user::handle_logout))
                     .route("/change_pass", web::post().to(user::handle_password_change))
+                    .route(
+                        "/notifications",
+                        web::get().to(notification::handle_get_mail_settings),
+                    )
+                    .route(
+                        "/notifications",
+                        web::put().to(notification::handle_put_mail_settings),
+                    )
                     .route("/greet", web::get().to(user::handle_greet));
 
                 App::new()
+                    .wrap_fn(|req, srv| {
+                        let started = std::time::Instant::now();
+                        let method = req.method().to_string();
+                        let response = srv.call(req);
+                        async move {
+                            let response = response.await?;
+                            // route pattern, so path parameters do not blow up label count
+                            let route = response
+                                .request()
+                                .match_pattern()
+                                .unwrap_or_else(|| "unmatched".to_string());
+                            metrics::observe_http_request(
+                                &method,
+                                &route,
+                                response.status().as_u16(),
+                                started.elapsed(),
+                            );
+                            Ok(response)
+                        }
+                    })
                     .wrap(session_middleware)
                     .wrap(cors)
                     .app_data(server_data)
@@ -214,6 +319,7 @@ async fn main() -> std::io::Result<()> {
                     .route("/dashboard", web::get().to(redirect_to_dashboard))
                     .route("/dashboard/{_:.*}", web::get().to(dashboard_serve))
                     .route("/service/update", web::post().to(update::push_update))
+                    .route("/metrics", web::get().to(metrics::handle_metrics))
                     .service(api_scope)
             })
             .workers(threads.unwrap_or(std::thread::available_parallelism().unwrap().into()))
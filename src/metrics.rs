@@ -0,0 +1,173 @@
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramTimer, HistogramVec,
+    IntCounterVec, TextEncoder,
+};
+use std::net::IpAddr;
+use std::time::Duration;
+
+lazy_static! {
+    /// When set, /metrics requires `Authorization: Bearer <token>`
+    static ref METRICS_TOKEN: Option<String> = std::env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    /// Peer addresses allowed to read /metrics without token, none by default.
+    /// Behind a reverse proxy every request comes from the proxy address, use the token there.
+    static ref METRICS_ALLOWED_IPS: Vec<IpAddr> = std::env::var("METRICS_ALLOWED_IPS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "web_portal_http_requests_total",
+        "HTTP requests by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "web_portal_http_request_duration_seconds",
+        "HTTP request latency by route",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "web_portal_logins_total",
+        "Login attempts by result",
+        &["result"]
+    )
+    .unwrap();
+    // Scanner and RPC counters are kept by the process running the scan, the scan command
+    // exports them with --metrics-addr
+    static ref BLOCKS_INSPECTED: IntCounterVec = register_int_counter_vec!(
+        "web_portal_scanner_blocks_inspected_total",
+        "Blocks inspected by the scanner by coverage status",
+        &["status"]
+    )
+    .unwrap();
+    static ref ANOMALIES: IntCounterVec = register_int_counter_vec!(
+        "web_portal_scanner_anomalies_total",
+        "Blocks where found transfers do not explain the balance change",
+        &["kind"]
+    )
+    .unwrap();
+    static ref RPC_CALLS: IntCounterVec = register_int_counter_vec!(
+        "web_portal_rpc_calls_total",
+        "JSON-RPC calls (including retries) by method",
+        &["method"]
+    )
+    .unwrap();
+    static ref RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "web_portal_rpc_errors_total",
+        "Failed JSON-RPC calls by method and kind of error",
+        &["method", "kind"]
+    )
+    .unwrap();
+    static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "web_portal_webhook_deliveries_total",
+        "Webhook delivery attempts by resulting status",
+        &["status"]
+    )
+    .unwrap();
+    static ref MAILS_SENT: IntCounterVec = register_int_counter_vec!(
+        "web_portal_mails_sent_total",
+        "Mails sent by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "web_portal_db_query_duration_seconds",
+        "SQLite query latency by operation",
+        &["query"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap();
+}
+
+pub fn observe_http_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn inc_login(success: bool) {
+    LOGINS
+        .with_label_values(&[if success { "success" } else { "failure" }])
+        .inc();
+}
+
+pub fn inc_blocks_inspected(status: &str) {
+    BLOCKS_INSPECTED.with_label_values(&[status]).inc();
+}
+
+pub fn inc_anomaly(kind: &str) {
+    ANOMALIES.with_label_values(&[kind]).inc();
+}
+
+pub fn inc_rpc_call(method: &str) {
+    RPC_CALLS.with_label_values(&[method]).inc();
+}
+
+pub fn inc_rpc_error(method: &str, kind: &str) {
+    RPC_ERRORS.with_label_values(&[method, kind]).inc();
+}
+
+pub fn inc_webhook_delivery(status: &str) {
+    WEBHOOK_DELIVERIES.with_label_values(&[status]).inc();
+}
+
+pub fn inc_mail_sent(kind: &str) {
+    MAILS_SENT.with_label_values(&[kind]).inc();
+}
+
+/// Observes query latency when dropped
+pub fn db_query_timer(query: &str) -> HistogramTimer {
+    DB_QUERY_DURATION.with_label_values(&[query]).start_timer()
+}
+
+/// Denied unless METRICS_TOKEN or METRICS_ALLOWED_IPS is configured
+fn is_metrics_access_allowed(req: &HttpRequest) -> bool {
+    if let Some(token) = METRICS_TOKEN.as_ref() {
+        let bearer = req
+            .headers()
+            .get(actix_web::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer == Some(token.as_str()) {
+            return true;
+        }
+    }
+    req.peer_addr()
+        .is_some_and(|addr| METRICS_ALLOWED_IPS.contains(&addr.ip()))
+}
+
+/// Server with /metrics only, for commands running without the web server
+pub fn metrics_server(addr: &str) -> std::io::Result<Server> {
+    log::info!("Serving metrics on {}", addr);
+    Ok(
+        HttpServer::new(|| App::new().route("/metrics", web::get().to(handle_metrics)))
+            .workers(1)
+            .disable_signals()
+            .bind(addr)?
+            .run(),
+    )
+}
+
+pub async fn handle_metrics(req: HttpRequest) -> HttpResponse {
+    if !is_metrics_access_allowed(&req) {
+        return HttpResponse::Forbidden().body("Metrics access not allowed");
+    }
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        log::error!("Error encoding metrics: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
@@ -0,0 +1,284 @@
+use crate::db::model::price::PriceDbObj;
+use crate::db::model::transaction::{wei, BlockDbObj, WEI_IN_ETH};
+use crate::db::ops::price::{get_prices, upsert_price};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use clap::{Parser, ValueEnum};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Sqlite, SqlitePool};
+use std::path::PathBuf;
+
+/// Price older than that is not used for valuation, covers daily files with a missing day
+const PRICE_MAX_AGE: chrono::TimeDelta = chrono::TimeDelta::hours(48);
+
+lazy_static! {
+    /// Currency used for valuation when the request does not ask for one
+    pub static ref PRICE_CURRENCY: String = std::env::var("PRICE_CURRENCY")
+        .unwrap_or("USD".to_string())
+        .to_uppercase();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PriceFileFormat {
+    Csv,
+    Json,
+}
+
+/// Timestamp as written in the price file, JSON files often have plain unix numbers
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RecordTimestamp {
+    Number(i64),
+    Text(String),
+}
+
+impl std::fmt::Display for RecordTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordTimestamp::Number(number) => write!(f, "{number}"),
+            RecordTimestamp::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+/// Row of an imported price file. Currency column is optional when given on command line.
+#[derive(Deserialize, Debug)]
+struct PriceRecord {
+    timestamp: RecordTimestamp,
+    price: f64,
+    #[serde(default)]
+    currency: Option<String>,
+}
+
+/// Accepts unix seconds (or milliseconds), RFC 3339, `YYYY-MM-DD HH:MM:SS` and `YYYY-MM-DD` in UTC
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(unix) = value.parse::<i64>() {
+        // milliseconds, as exported by most price APIs
+        if unix > 100_000_000_000 {
+            return DateTime::from_timestamp(unix / 1000, 0);
+        }
+        return DateTime::from_timestamp(unix, 0);
+    }
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time.with_timezone(&Utc));
+    }
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        return Some(date_time.and_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date_time| date_time.and_utc())
+}
+
+fn parse_price_records(
+    content: &str,
+    format: PriceFileFormat,
+    currency: Option<&str>,
+) -> Result<Vec<PriceDbObj>, WebPortalError> {
+    let records: Vec<PriceRecord> = match format {
+        PriceFileFormat::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| err_custom_create!("Invalid price CSV: {}", e))?,
+        PriceFileFormat::Json => serde_json::from_str(content)
+            .map_err(|e| err_custom_create!("Invalid price JSON: {}", e))?,
+    };
+
+    records
+        .into_iter()
+        .map(|record| {
+            let currency = record
+                .currency
+                .as_deref()
+                .or(currency)
+                .ok_or(err_custom_create!(
+                    "No currency for price at {}, add currency column or pass --currency",
+                    record.timestamp
+                ))?
+                .to_uppercase();
+            let timestamp = parse_timestamp(&record.timestamp.to_string())
+                .ok_or(err_custom_create!("Invalid timestamp {}", record.timestamp))?;
+            if !record.price.is_finite() || record.price <= 0.0 {
+                return Err(err_custom_create!(
+                    "Invalid price {} at {}",
+                    record.price,
+                    record.timestamp
+                ));
+            }
+            Ok(PriceDbObj {
+                currency,
+                timestamp,
+                price: record.price,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ImportPricesCommand {
+    /// CSV with header or JSON array, with `timestamp`, `price` and optionally `currency` fields
+    #[arg(long)]
+    file: PathBuf,
+    /// Currency of prices in the file, when it has no currency column
+    #[arg(long)]
+    currency: Option<String>,
+    /// Detected from file extension when not given
+    #[arg(long, value_enum)]
+    format: Option<PriceFileFormat>,
+}
+
+pub async fn import_prices_command(
+    conn: SqlitePool,
+    import_command: ImportPricesCommand,
+) -> Result<(), WebPortalError> {
+    let ImportPricesCommand {
+        file,
+        currency,
+        format,
+    } = import_command;
+
+    let format = match format {
+        Some(format) => format,
+        None => match file.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => PriceFileFormat::Csv,
+            Some("json") => PriceFileFormat::Json,
+            _ => {
+                return Err(err_custom_create!(
+                    "Cannot detect format of {}, pass --format",
+                    file.display()
+                ))
+            }
+        },
+    };
+    let content = std::fs::read_to_string(&file)
+        .map_err(|e| err_custom_create!("Error reading {}: {}", file.display(), e))?;
+    let prices = parse_price_records(&content, format, currency.as_deref())?;
+
+    let mut db_tx = conn
+        .begin()
+        .await
+        .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+    for price in &prices {
+        upsert_price(&mut *db_tx, price)
+            .await
+            .map_err(|e| err_custom_create!("Error saving price: {}", e))?;
+    }
+    db_tx
+        .commit()
+        .await
+        .map_err(|e| err_custom_create!("Error committing prices: {}", e))?;
+    log::info!("Imported {} prices from {}", prices.len(), file.display());
+    Ok(())
+}
+
+/// Prices of one currency, ordered by timestamp
+struct PriceSeries {
+    prices: Vec<PriceDbObj>,
+}
+
+impl PriceSeries {
+    /// Last known price at given time, if it is not too old
+    fn price_at(&self, timestamp: DateTime<Utc>) -> Option<f64> {
+        let idx = self
+            .prices
+            .partition_point(|price| price.timestamp <= timestamp);
+        let price = self.prices.get(idx.checked_sub(1)?)?;
+        (timestamp - price.timestamp <= PRICE_MAX_AGE).then_some(price.price)
+    }
+}
+
+fn wei_to_fiat(amount: &str, price: f64) -> f64 {
+    wei(amount) as f64 / WEI_IN_ETH as f64 * price
+}
+
+/// Block amounts valued at the block timestamp
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FiatValue {
+    pub currency: String,
+    /// Price of one ETH used for valuation
+    pub price: f64,
+    pub consensus_reward: f64,
+    pub mev_reward: f64,
+    pub amount_incoming: f64,
+    pub amount_outgoing: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ValuedBlock {
+    #[serde(flatten)]
+    pub block: BlockDbObj,
+    /// None when there is no price for the block time
+    pub fiat: Option<FiatValue>,
+}
+
+/// Value blocks in given currency, blocks can be in any order
+pub async fn value_blocks<'c, E>(
+    conn: E,
+    currency: &str,
+    blocks: Vec<BlockDbObj>,
+) -> Result<Vec<ValuedBlock>, WebPortalError>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let currency = currency.to_uppercase();
+    let first = blocks.iter().map(|block| block.timestamp).min();
+    let last = blocks.iter().map(|block| block.timestamp).max();
+    let series = match (first, last) {
+        (Some(first), Some(last)) => PriceSeries {
+            prices: get_prices(conn, &currency, first - PRICE_MAX_AGE, last)
+                .await
+                .map_err(|e| err_custom_create!("Error getting prices: {}", e))?,
+        },
+        _ => PriceSeries { prices: Vec::new() },
+    };
+
+    Ok(blocks
+        .into_iter()
+        .map(|block| {
+            let fiat = series.price_at(block.timestamp).map(|price| FiatValue {
+                currency: currency.clone(),
+                price,
+                consensus_reward: wei_to_fiat(&block.consensus_reward, price),
+                mev_reward: wei_to_fiat(&block.mev_reward, price),
+                amount_incoming: wei_to_fiat(&block.amount_incoming, price),
+                amount_outgoing: wei_to_fiat(&block.amount_outgoing, price),
+            });
+            ValuedBlock { block, fiat }
+        })
+        .collect())
+}
+
+#[test]
+fn parse_prices_test() {
+    let csv = "timestamp,price\n2024-06-01,3800.5\n1717286400,3810\n2024-06-03T00:00:00Z, 3820\n";
+    let prices = parse_price_records(csv, PriceFileFormat::Csv, Some("eur")).unwrap();
+    assert_eq!(prices.len(), 3);
+    assert!(prices.iter().all(|price| price.currency == "EUR"));
+    assert_eq!(prices[1].timestamp, parse_timestamp("2024-06-02").unwrap());
+
+    let json = r#"[{"timestamp": "1717200000000", "price": 3700, "currency": "usd"}]"#;
+    let prices_usd = parse_price_records(json, PriceFileFormat::Json, None).unwrap();
+    assert_eq!(prices_usd[0].timestamp, prices[0].timestamp);
+    assert_eq!(prices_usd[0].currency, "USD");
+    let json = r#"[{"timestamp": 1717200000, "price": 3700}, {"timestamp": 1717286400000, "price": 3710}]"#;
+    let prices_numeric = parse_price_records(json, PriceFileFormat::Json, Some("usd")).unwrap();
+    assert_eq!(prices_numeric[0].timestamp, prices[0].timestamp);
+    assert_eq!(prices_numeric[1].timestamp, prices[1].timestamp);
+    assert!(parse_price_records(csv, PriceFileFormat::Csv, None).is_err());
+
+    let series = PriceSeries { prices };
+    let at = |s: &str| series.price_at(parse_timestamp(s).unwrap());
+    assert_eq!(at("2024-05-31 23:59:59"), None);
+    assert_eq!(at("2024-06-02 12:00:00"), Some(3810.0));
+    assert_eq!(at("2024-06-04 23:00:00"), Some(3820.0));
+    assert_eq!(at("2024-06-05 01:00:00"), None);
+    assert_eq!(wei_to_fiat("500000000000000000", 3800.0), 1900.0);
+}
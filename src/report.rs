@@ -0,0 +1,386 @@
+use crate::db::model::transaction::{wei, WEI_IN_ETH};
+use crate::db::ops::transaction::get_blocks;
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::price::{value_blocks, ValuedBlock, PRICE_CURRENCY};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use web3::types::Address;
+
+/// Which acquired ETH is considered spent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostBasisMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    Average,
+}
+
+impl Display for CostBasisMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CostBasisMethod::Fifo => write!(f, "fifo"),
+            CostBasisMethod::Lifo => write!(f, "lifo"),
+            CostBasisMethod::Average => write!(f, "average"),
+        }
+    }
+}
+
+/// ETH acquired at once, price is per ETH
+#[derive(Debug, Clone)]
+struct Lot {
+    amount: i128,
+    price: f64,
+}
+
+/// Held ETH lots, for average method there is always at most one lot
+#[derive(Debug)]
+struct Inventory {
+    method: CostBasisMethod,
+    lots: VecDeque<Lot>,
+}
+
+impl Inventory {
+    fn new(method: CostBasisMethod) -> Self {
+        Inventory {
+            method,
+            lots: VecDeque::new(),
+        }
+    }
+
+    fn acquire(&mut self, amount: i128, price: f64) {
+        if amount <= 0 {
+            return;
+        }
+        match (self.method, self.lots.front_mut()) {
+            (CostBasisMethod::Average, Some(lot)) => {
+                let total = lot.amount + amount;
+                lot.price = (lot.price * lot.amount as f64 + price * amount as f64) / total as f64;
+                lot.amount = total;
+            }
+            _ => self.lots.push_back(Lot { amount, price }),
+        }
+    }
+
+    /// Remove amount from held lots, returns its cost basis
+    fn dispose(&mut self, mut amount: i128) -> f64 {
+        let mut cost = 0.0;
+        while amount > 0 {
+            let lot = match self.method {
+                CostBasisMethod::Lifo => self.lots.back_mut(),
+                CostBasisMethod::Fifo | CostBasisMethod::Average => self.lots.front_mut(),
+            };
+            let Some(lot) = lot else {
+                log::warn!("Disposing {} wei more than acquired, no cost basis", amount);
+                break;
+            };
+            let taken = amount.min(lot.amount);
+            cost += taken as f64 / WEI_IN_ETH as f64 * lot.price;
+            lot.amount -= taken;
+            amount -= taken;
+            if lot.amount == 0 {
+                match self.method {
+                    CostBasisMethod::Lifo => self.lots.pop_back(),
+                    CostBasisMethod::Fifo | CostBasisMethod::Average => self.lots.pop_front(),
+                };
+            }
+        }
+        cost
+    }
+
+    fn cost_basis(&self) -> f64 {
+        self.lots
+            .iter()
+            .map(|lot| lot.amount as f64 / WEI_IN_ETH as f64 * lot.price)
+            .sum()
+    }
+}
+
+/// Amounts valued at block time, only present when every needed block has a price
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FiatReport {
+    pub currency: String,
+    pub consensus_income: f64,
+    pub mev_income: f64,
+    pub transfers_in: f64,
+    pub transfers_out: f64,
+    pub fees: f64,
+    /// Market value of ETH sent out and spent on fees
+    pub proceeds: f64,
+    /// Cost of ETH sent out and spent on fees, by cost basis method
+    pub cost_basis: f64,
+    pub realized_gain: f64,
+    /// Cost of ETH held at the end of the year
+    pub closing_cost_basis: f64,
+}
+
+/// Wei amounts are decimal strings, like in the blocks API
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct YearReport {
+    pub addresses: Vec<String>,
+    pub year: i32,
+    pub method: CostBasisMethod,
+    pub opening_balance: String,
+    pub closing_balance: String,
+    pub consensus_income: String,
+    pub mev_income: String,
+    pub transfers_in: String,
+    pub transfers_out: String,
+    /// Balance decrease not explained by transfers, that is gas paid by the address
+    pub fees: String,
+    pub blocks: usize,
+    /// Blocks up to the end of the year without price, fiat report needs all of them
+    pub missing_prices: usize,
+    pub fiat: Option<FiatReport>,
+}
+
+fn year_bounds(year: i32) -> Result<(DateTime<Utc>, DateTime<Utc>), WebPortalError> {
+    let start = |year| {
+        NaiveDate::from_ymd_opt(year, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date_time| date_time.and_utc())
+            .ok_or(err_custom_create!("Invalid year {}", year))
+    };
+    Ok((start(year)?, start(year + 1)?))
+}
+
+/// Build report from blocks of every address of the group, ordered by block number
+fn build_report(
+    addresses: Vec<String>,
+    year: i32,
+    method: CostBasisMethod,
+    currency: &str,
+    blocks_per_address: Vec<Vec<ValuedBlock>>,
+) -> Result<YearReport, WebPortalError> {
+    let (year_start, year_end) = year_bounds(year)?;
+    let mut opening_balance = 0_i128;
+    let mut closing_balance = 0_i128;
+    let (mut consensus, mut mev, mut incoming, mut outgoing, mut fees) = (0, 0, 0, 0, 0);
+    let mut blocks_in_year = 0;
+    let mut missing_prices = 0;
+    let mut fiat = FiatReport {
+        currency: currency.to_string(),
+        ..Default::default()
+    };
+
+    for blocks in blocks_per_address {
+        let mut inventory = Inventory::new(method);
+        let mut balance_before_year = None;
+        let mut balance_at_end = 0_i128;
+        for (idx, valued) in blocks.iter().enumerate() {
+            let block = &valued.block;
+            if block.timestamp >= year_end {
+                break;
+            }
+            let price = valued.fiat.as_ref().map(|fiat| fiat.price);
+            if price.is_none() {
+                missing_prices += 1;
+            }
+            let price = price.unwrap_or_default();
+            let in_year = block.timestamp >= year_start;
+
+            let balance_diff = wei(&block.balance_diff);
+            if idx == 0 {
+                // balance from before the scan has no known cost, use first price we have
+                inventory.acquire(wei(&block.balance) - balance_diff, price);
+            }
+            if in_year && balance_before_year.is_none() {
+                balance_before_year = Some(wei(&block.balance) - balance_diff);
+            }
+            balance_at_end = wei(&block.balance);
+
+            let block_consensus = wei(&block.consensus_reward);
+            let block_mev = wei(&block.mev_reward);
+            let block_incoming = wei(&block.amount_incoming);
+            let block_outgoing = wei(&block.amount_outgoing);
+            let block_fees =
+                (block_consensus + block_mev + block_incoming - block_outgoing - balance_diff)
+                    .max(0);
+
+            inventory.acquire(block_consensus + block_mev + block_incoming, price);
+            let cost = inventory.dispose(block_outgoing + block_fees);
+            if !in_year {
+                continue;
+            }
+            blocks_in_year += 1;
+            consensus += block_consensus;
+            mev += block_mev;
+            incoming += block_incoming;
+            outgoing += block_outgoing;
+            fees += block_fees;
+
+            let to_fiat = |amount: i128| amount as f64 / WEI_IN_ETH as f64 * price;
+            fiat.consensus_income += to_fiat(block_consensus);
+            fiat.mev_income += to_fiat(block_mev);
+            fiat.transfers_in += to_fiat(block_incoming);
+            fiat.transfers_out += to_fiat(block_outgoing);
+            fiat.fees += to_fiat(block_fees);
+            fiat.proceeds += to_fiat(block_outgoing + block_fees);
+            fiat.cost_basis += cost;
+        }
+        opening_balance += balance_before_year.unwrap_or(balance_at_end);
+        closing_balance += balance_at_end;
+        fiat.closing_cost_basis += inventory.cost_basis();
+    }
+    fiat.realized_gain = fiat.proceeds - fiat.cost_basis;
+
+    Ok(YearReport {
+        addresses,
+        year,
+        method,
+        opening_balance: opening_balance.to_string(),
+        closing_balance: closing_balance.to_string(),
+        consensus_income: consensus.to_string(),
+        mev_income: mev.to_string(),
+        transfers_in: incoming.to_string(),
+        transfers_out: outgoing.to_string(),
+        fees: fees.to_string(),
+        blocks: blocks_in_year,
+        missing_prices,
+        fiat: (missing_prices == 0).then_some(fiat),
+    })
+}
+
+/// Report of one tax year (calendar year in UTC) for an address or a group of addresses
+pub async fn get_year_report(
+    conn: &SqlitePool,
+    addresses: &[String],
+    year: i32,
+    method: CostBasisMethod,
+    currency: Option<&str>,
+) -> Result<YearReport, WebPortalError> {
+    let currency = currency.unwrap_or(&PRICE_CURRENCY).to_uppercase();
+    let mut blocks_per_address = Vec::new();
+    for address in addresses {
+        let blocks = get_blocks(conn, address)
+            .await
+            .map_err(|e| err_custom_create!("Error getting blocks of {}: {}", address, e))?;
+        blocks_per_address.push(value_blocks(conn, &currency, blocks).await?);
+    }
+    build_report(
+        addresses.to_vec(),
+        year,
+        method,
+        &currency,
+        blocks_per_address,
+    )
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ReportCommand {
+    /// Address to report, repeat or separate with commas for a group
+    #[arg(long, required = true, value_delimiter = ',')]
+    address: Vec<Address>,
+    #[arg(long)]
+    year: i32,
+    #[arg(long, value_enum, default_value_t = CostBasisMethod::default())]
+    method: CostBasisMethod,
+    /// Fiat currency of imported prices, PRICE_CURRENCY when not given
+    #[arg(long)]
+    currency: Option<String>,
+}
+
+pub async fn report_command(
+    conn: SqlitePool,
+    report_command: ReportCommand,
+) -> Result<(), WebPortalError> {
+    let ReportCommand {
+        address,
+        year,
+        method,
+        currency,
+    } = report_command;
+
+    let addresses: Vec<String> = address.iter().map(|a| format!("{a:#x}")).collect();
+    let report = get_year_report(&conn, &addresses, year, method, currency.as_deref()).await?;
+    if report.fiat.is_none() {
+        log::warn!(
+            "{} blocks without price, fiat values are not reported",
+            report.missing_prices
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report)
+            .map_err(|e| err_custom_create!("Error serializing report: {}", e))?
+    );
+    Ok(())
+}
+
+#[test]
+fn cost_basis_test() {
+    let eth = WEI_IN_ETH;
+    let costs = |method| {
+        let mut inventory = Inventory::new(method);
+        inventory.acquire(eth, 1000.0);
+        inventory.acquire(eth, 3000.0);
+        (inventory.dispose(eth * 3 / 2), inventory.cost_basis())
+    };
+    assert_eq!(costs(CostBasisMethod::Fifo), (2500.0, 1500.0));
+    assert_eq!(costs(CostBasisMethod::Lifo), (3500.0, 500.0));
+    assert_eq!(costs(CostBasisMethod::Average), (3000.0, 1000.0));
+}
+
+#[test]
+fn build_report_test() {
+    use crate::db::model::transaction::BlockDbObj;
+    use crate::price::FiatValue;
+
+    let eth = WEI_IN_ETH;
+    let block =
+        |date: &str, balance: i128, diff: i128, consensus: i128, outgoing: i128| ValuedBlock {
+            block: BlockDbObj {
+                balance: balance.to_string(),
+                balance_diff: diff.to_string(),
+                consensus_reward: consensus.to_string(),
+                amount_outgoing: outgoing.to_string(),
+                ..BlockDbObj::test_block("0x01", 0, date)
+            },
+            fiat: Some(FiatValue {
+                currency: "USD".to_string(),
+                price: 2000.0,
+                consensus_reward: 0.0,
+                mev_reward: 0.0,
+                amount_incoming: 0.0,
+                amount_outgoing: 0.0,
+            }),
+        };
+    let blocks = vec![
+        block("2023-12-01T00:00:00Z", 2 * eth, eth, eth, 0),
+        block("2024-03-01T00:00:00Z", 3 * eth, eth, eth, 0),
+        // one eth sent out plus 0.01 eth gas
+        block(
+            "2024-06-01T00:00:00Z",
+            2 * eth - eth / 100,
+            -eth - eth / 100,
+            0,
+            eth,
+        ),
+        block("2025-01-01T00:00:00Z", 3 * eth, eth, eth, 0),
+    ];
+    let report = build_report(
+        vec!["0x01".to_string()],
+        2024,
+        CostBasisMethod::Fifo,
+        "USD",
+        vec![blocks],
+    )
+    .unwrap();
+    assert_eq!(report.opening_balance, (2 * eth).to_string());
+    assert_eq!(report.closing_balance, (2 * eth - eth / 100).to_string());
+    assert_eq!(report.consensus_income, eth.to_string());
+    assert_eq!(report.transfers_out, eth.to_string());
+    assert_eq!(report.fees, (eth / 100).to_string());
+    assert_eq!(report.blocks, 2);
+    let fiat = report.fiat.unwrap();
+    assert_eq!(fiat.consensus_income, 2000.0);
+    assert!((fiat.fees - 20.0).abs() < 1e-9);
+    assert!(fiat.realized_gain.abs() < 1e-9);
+}
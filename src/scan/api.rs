@@ -1,10 +1,43 @@
+use crate::db::model::transaction::TxDbObj;
+use crate::db::model::webhook::WebhookDbObj;
 use crate::db::model::UserDbObj;
-use crate::db::ops::transaction::{get_all_scans, get_blocks, get_scan};
+use crate::db::ops::label::{
+    delete_address_label, get_address_label, get_address_labels, upsert_address_label,
+};
+use crate::db::ops::transaction::{
+    delete_scan, get_all_scans, get_blocks_page, get_failed_blocks, get_scan, get_txs, insert_scan,
+    BlockActivity, BlockFilter, BlockSort, BlocksCursor, BlocksPageQuery, TxsCursor,
+};
+use crate::db::ops::webhook::{
+    delete_webhook, get_webhook, get_webhook_deliveries, get_webhooks, insert_webhook,
+};
+use crate::export::{parse_filter_timestamp, spawn_export, ExportFormat, ExportKind};
+use crate::price::ValuedBlock;
+use crate::price::{value_blocks, PRICE_CURRENCY};
+use crate::report::{get_year_report, CostBasisMethod};
+use crate::scan::counterparty::get_counterparties;
+use crate::scan::coverage::get_coverage_report;
+use crate::scan::details::{get_block_with_txs, get_tx_with_traces};
+use crate::scan::discovery::DiscoveryStrategy;
+use crate::scan::finality::FinalityPolicy;
+use crate::scan::labels::{
+    attach_labels, export_labels_csv, get_label_map, import_labels_csv, label_from_input,
+    normalize_address, LabelInput,
+};
+use crate::scan::queue::{ScanJob, ScanJobKind};
+use crate::scan::run::{create_web3, find_block_by_timestamp, new_scan_obj, ScanOptions};
+use crate::summary::{get_summary, parse_tz, Bucket};
+use crate::webhook::{webhook_from_input, WebhookInput};
 use crate::ServerData;
 use actix_session::Session;
 use actix_web::web::Data;
 use actix_web::{web, HttpResponse, Scope};
+use clap::ValueEnum;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::str::FromStr;
+use web3::types::Address;
 
 lazy_static! {
     static ref IGNORE_SCAN_API_LOGIN: bool = {
@@ -28,17 +61,61 @@ macro_rules! login_check {
     };
 }
 
+/// Login check for endpoints changing data, IGNORE_SCAN_API_LOGIN opens only the read-only API
+macro_rules! write_login_check {
+    ($session:expr) => {
+        if $session.get::<UserDbObj>("user").unwrap_or(None).is_none() {
+            return HttpResponse::Unauthorized().body("Not logged in");
+        }
+    };
+}
+
+#[derive(Deserialize)]
+pub struct LabelsQuery {
+    /// Add labels of known addresses to the response objects
+    #[serde(default)]
+    labels: bool,
+}
+
+async fn json_with_labels<T: Serialize>(
+    db: &SqlitePool,
+    value: &T,
+    with_labels: bool,
+) -> HttpResponse {
+    if !with_labels {
+        return HttpResponse::Ok().json(value);
+    }
+    let labels = match get_label_map(db).await {
+        Ok(labels) => labels,
+        Err(e) => {
+            log::error!("Error getting labels: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    match serde_json::to_value(value) {
+        Ok(mut value) => {
+            attach_labels(&mut value, &labels);
+            HttpResponse::Ok().json(value)
+        }
+        Err(e) => {
+            log::error!("Error serializing response: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 async fn web_get_scan_info(
     data: Data<Box<ServerData>>,
     address: web::Path<String>,
+    labels: web::Query<LabelsQuery>,
     session: Session,
 ) -> HttpResponse {
     login_check!(session);
 
     let db = data.db_connection.lock().await;
 
-    match get_scan(&db, &address).await {
-        Ok(scan_info) => HttpResponse::Ok().json(scan_info),
+    match get_scan(&*db, &address).await {
+        Ok(scan_info) => json_with_labels(&db, &scan_info, labels.labels).await,
         Err(e) => {
             log::error!("Error getting scan info: {}", e);
             HttpResponse::InternalServerError().finish()
@@ -46,13 +123,17 @@ async fn web_get_scan_info(
     }
 }
 
-async fn web_get_all_scans(data: Data<Box<ServerData>>, session: Session) -> HttpResponse {
+async fn web_get_all_scans(
+    data: Data<Box<ServerData>>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
     login_check!(session);
 
     let db = data.db_connection.lock().await;
 
-    match get_all_scans(&db).await {
-        Ok(scans) => HttpResponse::Ok().json(scans),
+    match get_all_scans(&*db).await {
+        Ok(scans) => json_with_labels(&db, &scans, labels.labels).await,
         Err(e) => {
             log::error!("Error getting scan info: {}", e);
             HttpResponse::InternalServerError().finish()
@@ -60,29 +141,965 @@ async fn web_get_all_scans(data: Data<Box<ServerData>>, session: Session) -> Htt
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateScanRequest {
+    address: String,
+    block_start: Option<u64>,
+    /// Timestamp, scan starts at the first block not older than it. Used instead of block_start.
+    from: Option<String>,
+    /// Exclusive, last final block when not given
+    block_end: Option<u64>,
+    /// finalized, safe or depth:<blocks>
+    finality: Option<String>,
+    /// balance-probe or trace-filter
+    discovery: Option<String>,
+}
+
+async fn web_create_scan(
+    data: Data<Box<ServerData>>,
+    request: web::Json<CreateScanRequest>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let Ok(address) = Address::from_str(&request.address) else {
+        return HttpResponse::BadRequest().body("Invalid address");
+    };
+    let finality = match request.finality.as_deref().map(FinalityPolicy::from_str) {
+        Some(Ok(finality)) => finality,
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+        None => FinalityPolicy::default(),
+    };
+    let discovery = match request
+        .discovery
+        .as_deref()
+        .map(|discovery| DiscoveryStrategy::from_str(discovery, true))
+    {
+        Some(Ok(discovery)) => discovery,
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+        None => DiscoveryStrategy::default(),
+    };
+    let from = match (request.block_start, &request.from) {
+        (Some(_), None) => None,
+        (None, Some(from)) => match parse_filter_timestamp(from) {
+            Ok(from) => Some(from),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        _ => return HttpResponse::BadRequest().body("Give either blockStart or from"),
+    };
+
+    let db = data.db_connection.lock().await.clone();
+    match get_scan(&db, &format!("{address:#x}")).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Scan already exists"),
+        Err(e) => {
+            log::error!("Error getting scan info: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    let web3 = match create_web3() {
+        Ok(web3) => web3,
+        Err(e) => {
+            log::error!("Error creating web3: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let block_start = match (request.block_start, from) {
+        (Some(block_start), _) => block_start,
+        (None, Some(from)) => match find_block_by_timestamp(&web3, from).await {
+            Ok(block_start) => block_start,
+            Err(e) => return HttpResponse::BadRequest().body(e.inner.to_string()),
+        },
+        (None, None) => unreachable!("checked above"),
+    };
+    let last_final_block = match finality.last_final_block(&web3, None).await {
+        Ok(last_final_block) => last_final_block,
+        Err(e) => {
+            log::error!("Error getting last final block: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if block_start >= last_final_block {
+        return HttpResponse::BadRequest().body("Block start is not final yet");
+    }
+    if let Some(block_end) = request.block_end {
+        if block_end <= block_start || block_end > last_final_block {
+            return HttpResponse::BadRequest()
+                .body("Block end has to be after block start and final");
+        }
+    }
+
+    let scan = match new_scan_obj(&web3, address, block_start, finality, None).await {
+        Ok(scan) => scan,
+        Err(e) => {
+            log::error!("Error getting start block: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let scan = match insert_scan(&db, &scan).await {
+        Ok(scan) => scan,
+        // created by a concurrent request while the start block was looked up
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return HttpResponse::Conflict().body("Scan already exists")
+        }
+        Err(e) => {
+            log::error!("Error inserting scan: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let job = ScanJob {
+        address,
+        block_start,
+        block_end: request.block_end,
+        options: ScanOptions {
+            finality,
+            discovery,
+            trace_mode: None,
+            existing_only: true,
+            follow: false,
+        },
+        kind: ScanJobKind::Scan,
+    };
+    if let Err(e) = data.scan_queue.push(job) {
+        log::error!("Error queueing scan: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Accepted().json(scan)
+}
+
+/// Stops the scan if it is running and deletes it with all its blocks
+async fn web_delete_scan(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let Ok(address) = Address::from_str(&address) else {
+        return HttpResponse::BadRequest().body("Invalid address");
+    };
+    let db = data.db_connection.lock().await;
+    match get_scan(&*db, &format!("{address:#x}")).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body("Scan not found"),
+        Err(e) => {
+            log::error!("Error getting scan info: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    log::warn!("Deleting scan for address: {:#x}", address);
+    if let Err(e) = delete_scan(&*db, &format!("{address:#x}")).await {
+        log::error!("Error deleting scan: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    // after the delete, a job starting in between finds no row to scan
+    data.scan_queue.cancel(address);
+    HttpResponse::NoContent().finish()
+}
+
+/// Blocks returned when the request does not give limit
+const BLOCKS_DEFAULT_LIMIT: i64 = 1000;
+const BLOCKS_MAX_LIMIT: i64 = 10000;
+
+/// Block range and from/to times given as query parameters
+fn block_filter(
+    block_start: Option<i64>,
+    block_end: Option<i64>,
+    from: &Option<String>,
+    to: &Option<String>,
+) -> Result<BlockFilter, String> {
+    let parse_time =
+        |value: &Option<String>| value.as_deref().map(parse_filter_timestamp).transpose();
+    Ok(BlockFilter {
+        block_start,
+        block_end,
+        from: parse_time(from)?,
+        to: parse_time(to)?,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlocksQuery {
+    /// Fiat currency for valuation, PRICE_CURRENCY when not given
+    currency: Option<String>,
+    limit: Option<i64>,
+    /// nextCursor of the previous page
+    cursor: Option<String>,
+    block_start: Option<i64>,
+    /// Exclusive
+    block_end: Option<i64>,
+    from: Option<String>,
+    /// Exclusive
+    to: Option<String>,
+    /// Wei, largest of rewards and transferred amounts has to be at least that
+    min_amount: Option<String>,
+    /// Comma separated mev, withdrawals and transfers, blocks with any of them are returned
+    only: Option<String>,
+    /// blockNumber (default), balanceDiff, consensusReward, mevReward, amountIncoming or amountOutgoing
+    sort: Option<String>,
+    /// asc (default) or desc
+    order: Option<String>,
+}
+
+impl BlocksQuery {
+    fn to_page_query(&self) -> Result<BlocksPageQuery, String> {
+        let activities = match &self.only {
+            Some(only) => only
+                .split(',')
+                .map(|activity| match activity.trim() {
+                    "mev" => Ok(BlockActivity::Mev),
+                    "withdrawals" => Ok(BlockActivity::Withdrawals),
+                    "transfers" => Ok(BlockActivity::Transfers),
+                    other => Err(format!("Invalid only value {}", other)),
+                })
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let sort = match self.sort.as_deref() {
+            None | Some("blockNumber") => BlockSort::BlockNumber,
+            Some("balanceDiff") => BlockSort::BalanceDiff,
+            Some("consensusReward") => BlockSort::ConsensusReward,
+            Some("mevReward") => BlockSort::MevReward,
+            Some("amountIncoming") => BlockSort::AmountIncoming,
+            Some("amountOutgoing") => BlockSort::AmountOutgoing,
+            Some(other) => return Err(format!("Invalid sort {}", other)),
+        };
+        let descending = match self.order.as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(format!("Invalid order {}", other)),
+        };
+        let limit = self.limit.unwrap_or(BLOCKS_DEFAULT_LIMIT);
+        if !(1..=BLOCKS_MAX_LIMIT).contains(&limit) {
+            return Err(format!(
+                "Limit has to be between 1 and {}",
+                BLOCKS_MAX_LIMIT
+            ));
+        }
+        Ok(BlocksPageQuery {
+            filter: block_filter(self.block_start, self.block_end, &self.from, &self.to)?,
+            min_amount: self
+                .min_amount
+                .as_deref()
+                .map(|amount| {
+                    amount
+                        .parse::<u128>()
+                        .map_err(|_| format!("Invalid minAmount {}", amount))
+                })
+                .transpose()?,
+            activities,
+            sort,
+            descending,
+            cursor: self
+                .cursor
+                .as_deref()
+                .map(BlocksCursor::from_str)
+                .transpose()?,
+            limit,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BlocksPage {
+    blocks: Vec<ValuedBlock>,
+    /// Pass as cursor to get the next page, None on the last page
+    next_cursor: Option<String>,
+}
+
 async fn web_get_blocks(
     data: Data<Box<ServerData>>,
     address: web::Path<String>,
+    query: web::Query<BlocksQuery>,
+    labels: web::Query<LabelsQuery>,
     session: Session,
 ) -> HttpResponse {
     login_check!(session);
 
+    let page_query = match query.to_page_query() {
+        Ok(page_query) => page_query,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
     let db = data.db_connection.lock().await;
 
-    match get_blocks(&db, &address).await {
-        Ok(blocks) => HttpResponse::Ok().json(blocks),
+    let (blocks, next_cursor) = match get_blocks_page(&*db, &address, &page_query).await {
+        Ok(page) => page,
         Err(e) => {
             log::error!("Error getting scan info: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let currency = query.currency.as_deref().unwrap_or(&PRICE_CURRENCY);
+    match value_blocks(&*db, currency, blocks).await {
+        Ok(blocks) => {
+            json_with_labels(
+                &db,
+                &BlocksPage {
+                    blocks,
+                    next_cursor: next_cursor.map(|cursor| cursor.to_string()),
+                },
+                labels.labels,
+            )
+            .await
+        }
+        Err(e) => {
+            log::error!("Error valuing blocks: {}", e);
             HttpResponse::InternalServerError().finish()
         }
     }
 }
 
+async fn web_get_failed_blocks(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+
+    match get_failed_blocks(&*db, &address).await {
+        Ok(failed_blocks) => json_with_labels(&db, &failed_blocks, labels.labels).await,
+        Err(e) => {
+            log::error!("Error getting failed blocks: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_get_coverage(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+
+    match get_coverage_report(&db, &address).await {
+        Ok(Some(report)) => json_with_labels(&db, &report, labels.labels).await,
+        Ok(None) => HttpResponse::NotFound().body("Scan not found"),
+        Err(e) => {
+            log::error!("Error getting coverage: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FillGapRequest {
+    block_start: u64,
+    block_end: u64,
+}
+
+async fn web_fill_coverage_gap(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    request: web::Json<FillGapRequest>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let Ok(parsed_address) = Address::from_str(&address) else {
+        return HttpResponse::BadRequest().body("Invalid address");
+    };
+    let db = data.db_connection.lock().await.clone();
+    let scan = match get_scan(&db, &address).await {
+        Ok(Some(scan)) => scan,
+        Ok(None) => return HttpResponse::NotFound().body("Scan not found"),
+        Err(e) => {
+            log::error!("Error getting scan info: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if request.block_start >= request.block_end
+        || request.block_start < scan.first_block_number as u64
+        || request.block_end > scan.next_block_number as u64
+    {
+        return HttpResponse::BadRequest().body("Range is outside of scanned blocks");
+    }
+
+    // queued with the scans, so it never runs next to another job of the address
+    let job = ScanJob {
+        address: parsed_address,
+        block_start: request.block_start,
+        block_end: Some(request.block_end),
+        options: ScanOptions::default(),
+        kind: ScanJobKind::FillGap,
+    };
+    if let Err(e) = data.scan_queue.push(job) {
+        log::error!("Error queueing coverage gap fill: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Accepted().json(request.into_inner())
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    /// Comma separated addresses of the group
+    addresses: String,
+    #[serde(default)]
+    method: CostBasisMethod,
+    currency: Option<String>,
+}
+
+async fn web_get_year_report(
+    data: Data<Box<ServerData>>,
+    year: web::Path<i32>,
+    query: web::Query<ReportQuery>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let mut addresses = Vec::new();
+    for address in query.addresses.split(',') {
+        let Ok(address) = Address::from_str(address.trim()) else {
+            return HttpResponse::BadRequest().body("Invalid address");
+        };
+        addresses.push(format!("{address:#x}"));
+    }
+    let db = data.db_connection.lock().await;
+
+    match get_year_report(
+        &db,
+        &addresses,
+        *year,
+        query.method,
+        query.currency.as_deref(),
+    )
+    .await
+    {
+        Ok(report) => json_with_labels(&db, &report, labels.labels).await,
+        Err(e) => {
+            log::error!("Error generating report: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TxsQuery {
+    block_start: Option<i64>,
+    /// Exclusive
+    block_end: Option<i64>,
+    from: Option<String>,
+    /// Exclusive
+    to: Option<String>,
+    /// nextCursor of the previous page
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TxsPage {
+    txs: Vec<TxDbObj>,
+    /// Pass as cursor to get the next page, None on the last page
+    next_cursor: Option<String>,
+}
+
+async fn web_get_txs(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    query: web::Query<TxsQuery>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let filter = match block_filter(query.block_start, query.block_end, &query.from, &query.to) {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let limit = query.limit.unwrap_or(BLOCKS_DEFAULT_LIMIT);
+    if !(1..=BLOCKS_MAX_LIMIT).contains(&limit) {
+        return HttpResponse::BadRequest().body(format!(
+            "Limit has to be between 1 and {}",
+            BLOCKS_MAX_LIMIT
+        ));
+    }
+    let cursor = match query.cursor.as_deref().map(TxsCursor::from_str).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let db = data.db_connection.lock().await;
+
+    match get_txs(&*db, &address, &filter, cursor, limit).await {
+        Ok((txs, next_cursor)) => {
+            json_with_labels(
+                &db,
+                &TxsPage {
+                    txs,
+                    next_cursor: next_cursor.map(|cursor| cursor.to_string()),
+                },
+                labels.labels,
+            )
+            .await
+        }
+        Err(e) => {
+            log::error!("Error getting transactions: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_get_block(
+    data: Data<Box<ServerData>>,
+    path: web::Path<(String, i64)>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let (address, block_number) = path.into_inner();
+    let db = data.db_connection.lock().await;
+
+    match get_block_with_txs(&db, &address, block_number).await {
+        Ok(Some(block)) => json_with_labels(&db, &block, labels.labels).await,
+        Ok(None) => HttpResponse::NotFound().body("Block not found"),
+        Err(e) => {
+            log::error!("Error getting block: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn web_get_tx(
+    data: Data<Box<ServerData>>,
+    tx_hash: web::Path<String>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+
+    match get_tx_with_traces(&db, &tx_hash.to_lowercase()).await {
+        Ok(txs) if txs.is_empty() => HttpResponse::NotFound().body("Transaction not found"),
+        Ok(txs) => json_with_labels(&db, &txs, labels.labels).await,
+        Err(e) => {
+            log::error!("Error getting transaction: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Counterparties returned when the request does not give limit
+const COUNTERPARTIES_DEFAULT_LIMIT: usize = 100;
+const COUNTERPARTIES_MAX_LIMIT: usize = 10000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CounterpartiesQuery {
+    block_start: Option<i64>,
+    /// Exclusive
+    block_end: Option<i64>,
+    from: Option<String>,
+    /// Exclusive
+    to: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn web_get_counterparties(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    query: web::Query<CounterpartiesQuery>,
+    labels: web::Query<LabelsQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let filter = match block_filter(query.block_start, query.block_end, &query.from, &query.to) {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let limit = query.limit.unwrap_or(COUNTERPARTIES_DEFAULT_LIMIT);
+    if !(1..=COUNTERPARTIES_MAX_LIMIT).contains(&limit) {
+        return HttpResponse::BadRequest().body(format!(
+            "Limit has to be between 1 and {}",
+            COUNTERPARTIES_MAX_LIMIT
+        ));
+    }
+    let db = data.db_connection.lock().await;
+
+    match get_counterparties(&db, &address, &filter, limit).await {
+        Ok(counterparties) => json_with_labels(&db, &counterparties, labels.labels).await,
+        Err(e) => {
+            log::error!("Error getting counterparties: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SummaryQuery {
+    #[serde(default)]
+    bucket: Bucket,
+    /// IANA time zone of bucket boundaries, UTC when not given
+    tz: Option<String>,
+}
+
+async fn web_get_summary(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    query: web::Query<SummaryQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let tz = match parse_tz(query.tz.as_deref().unwrap_or("UTC")) {
+        Ok(tz) => tz,
+        Err(_) => return HttpResponse::BadRequest().body("Unknown time zone"),
+    };
+    let db = data.db_connection.lock().await.clone();
+
+    match get_summary(&db, &address, query.bucket, tz).await {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(e) => {
+            log::error!("Error getting summary: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    block_start: Option<i64>,
+    /// Exclusive
+    block_end: Option<i64>,
+    from: Option<String>,
+    /// Exclusive
+    to: Option<String>,
+}
+
+async fn web_export(
+    data: Data<Box<ServerData>>,
+    path: web::Path<(String, ExportKind)>,
+    query: web::Query<ExportQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let (address, kind) = path.into_inner();
+    let address = match normalize_address(&address) {
+        Ok(address) => address,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let filter = match block_filter(query.block_start, query.block_end, &query.from, &query.to) {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let db = data.db_connection.lock().await.clone();
+
+    let file_name = format!("{}-{}.{}", address, kind, query.format.extension());
+    let receiver = spawn_export(db, address, kind, query.format, filter);
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let chunk =
+            receiver.recv().await?.map(web::Bytes::from).map_err(|e| {
+                actix_web::error::ErrorInternalServerError(format!("Export failed: {e}"))
+            });
+        Some((chunk, receiver))
+    });
+    HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header((
+            actix_web::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{file_name}\""),
+        ))
+        .streaming(body)
+}
+
+async fn web_get_webhooks(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+    match get_webhooks(&*db, &address.to_lowercase()).await {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+        Err(e) => {
+            log::error!("Error getting webhooks: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Webhook with its secret, returned only when it is created
+#[derive(Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: WebhookDbObj,
+    secret: String,
+}
+
+async fn web_create_webhook(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    input: web::Json<WebhookInput>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let webhook = match webhook_from_input(&address, input.into_inner()) {
+        Ok(webhook) => webhook,
+        Err(e) => return HttpResponse::BadRequest().body(e.inner.to_string()),
+    };
+    let db = data.db_connection.lock().await;
+    match get_scan(&*db, &webhook.address).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body("Scan not found"),
+        Err(e) => {
+            log::error!("Error getting scan info: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    match insert_webhook(&*db, &webhook).await {
+        Ok(webhook) => HttpResponse::Created().json(CreatedWebhook {
+            secret: webhook.secret.clone(),
+            webhook,
+        }),
+        Err(e) => {
+            log::error!("Error saving webhook: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_delete_webhook(
+    data: Data<Box<ServerData>>,
+    path: web::Path<(String, String)>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let (address, id) = path.into_inner();
+    let db = data.db_connection.lock().await;
+    match delete_webhook(&*db, &address.to_lowercase(), &id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("Webhook not found"),
+        Err(e) => {
+            log::error!("Error deleting webhook: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Deliveries returned when the request does not give limit
+const WEBHOOK_DELIVERIES_DEFAULT_LIMIT: i64 = 50;
+const WEBHOOK_DELIVERIES_MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+struct WebhookDeliveriesQuery {
+    limit: Option<i64>,
+}
+
+async fn web_get_webhook_deliveries(
+    data: Data<Box<ServerData>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<WebhookDeliveriesQuery>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let (address, id) = path.into_inner();
+    let db = data.db_connection.lock().await;
+    match get_webhook(&*db, &id).await {
+        Ok(Some(webhook)) if webhook.address == address.to_lowercase() => {}
+        Ok(_) => return HttpResponse::NotFound().body("Webhook not found"),
+        Err(e) => {
+            log::error!("Error getting webhook: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    let limit = query.limit.unwrap_or(WEBHOOK_DELIVERIES_DEFAULT_LIMIT);
+    if !(1..=WEBHOOK_DELIVERIES_MAX_LIMIT).contains(&limit) {
+        return HttpResponse::BadRequest().body(format!(
+            "Limit has to be between 1 and {}",
+            WEBHOOK_DELIVERIES_MAX_LIMIT
+        ));
+    }
+    match get_webhook_deliveries(&*db, &id, limit).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(e) => {
+            log::error!("Error getting webhook deliveries: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_get_labels(data: Data<Box<ServerData>>, session: Session) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+    match get_address_labels(&*db).await {
+        Ok(labels) => HttpResponse::Ok().json(labels),
+        Err(e) => {
+            log::error!("Error getting labels: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_get_label(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    session: Session,
+) -> HttpResponse {
+    login_check!(session);
+
+    let Ok(address) = normalize_address(&address) else {
+        return HttpResponse::BadRequest().body("Invalid address");
+    };
+    let db = data.db_connection.lock().await;
+    match get_address_label(&*db, &address).await {
+        Ok(Some(label)) => HttpResponse::Ok().json(label),
+        Ok(None) => HttpResponse::NotFound().body("Label not found"),
+        Err(e) => {
+            log::error!("Error getting label: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_put_label(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    input: web::Json<LabelInput>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let label = match label_from_input(&address, input.into_inner()) {
+        Ok(label) => label,
+        Err(e) => return HttpResponse::BadRequest().body(e.inner.to_string()),
+    };
+    let db = data.db_connection.lock().await;
+    match upsert_address_label(&*db, &label).await {
+        Ok(label) => HttpResponse::Ok().json(label),
+        Err(e) => {
+            log::error!("Error saving label: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn web_delete_label(
+    data: Data<Box<ServerData>>,
+    address: web::Path<String>,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let Ok(address) = normalize_address(&address) else {
+        return HttpResponse::BadRequest().body("Invalid address");
+    };
+    let db = data.db_connection.lock().await;
+    match delete_address_label(&*db, &address).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("Label not found"),
+        Err(e) => {
+            log::error!("Error deleting label: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LabelImportResult {
+    imported: usize,
+}
+
+/// Body is CSV with address, name, category and notes columns
+async fn web_import_labels(
+    data: Data<Box<ServerData>>,
+    body: String,
+    session: Session,
+) -> HttpResponse {
+    write_login_check!(session);
+
+    let db = data.db_connection.lock().await;
+    match import_labels_csv(&db, &body).await {
+        Ok(imported) => HttpResponse::Ok().json(LabelImportResult { imported }),
+        Err(e) => HttpResponse::BadRequest().body(e.inner.to_string()),
+    }
+}
+
+async fn web_export_labels(data: Data<Box<ServerData>>, session: Session) -> HttpResponse {
+    login_check!(session);
+
+    let db = data.db_connection.lock().await;
+    match export_labels_csv(&db).await {
+        Ok(content) => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                actix_web::http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"labels.csv\"",
+            ))
+            .body(content),
+        Err(e) => {
+            log::error!("Error exporting labels: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub fn get_labels_scope() -> Scope {
+    Scope::new("/labels")
+        .route("", web::get().to(web_get_labels))
+        .route("import", web::post().to(web_import_labels))
+        .route("export", web::get().to(web_export_labels))
+        .route("{address}", web::get().to(web_get_label))
+        .route("{address}", web::put().to(web_put_label))
+        .route("{address}", web::delete().to(web_delete_label))
+}
+
 pub fn get_scan_scope() -> Scope {
     let api_scope = Scope::new("/scan");
 
     api_scope
         .route("{address}/info", web::get().to(web_get_scan_info))
         .route("{address}/blocks", web::get().to(web_get_blocks))
+        .route("{address}/failures", web::get().to(web_get_failed_blocks))
+        .route("{address}/coverage", web::get().to(web_get_coverage))
+        .route(
+            "{address}/coverage/fill",
+            web::post().to(web_fill_coverage_gap),
+        )
+        .route("{address}/summary", web::get().to(web_get_summary))
+        .route("{address}/txs", web::get().to(web_get_txs))
+        .route(
+            "{address}/counterparties",
+            web::get().to(web_get_counterparties),
+        )
+        .route("{address}/blocks/{number}", web::get().to(web_get_block))
+        .route("{address}/export/{kind}", web::get().to(web_export))
+        .route("{address}/webhooks", web::get().to(web_get_webhooks))
+        .route("{address}/webhooks", web::post().to(web_create_webhook))
+        .route(
+            "{address}/webhooks/{id}",
+            web::delete().to(web_delete_webhook),
+        )
+        .route(
+            "{address}/webhooks/{id}/deliveries",
+            web::get().to(web_get_webhook_deliveries),
+        )
+        .route("", web::post().to(web_create_scan))
         .route("all", web::get().to(web_get_all_scans))
+        .route("report/{year}", web::get().to(web_get_year_report))
+        .route("{address}", web::delete().to(web_delete_scan))
 }
@@ -1,20 +1,33 @@
-use crate::err_custom_create;
 use crate::error::WebPortalError;
+use crate::scan::rpc::{batch_call, rpc_call};
+use crate::scan::transport::ScanWeb3;
 use lazy_static::lazy_static;
+use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Mutex;
 use web3::types::{Address, BlockNumber, U256};
 
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<String, U256>> = Mutex::new(HashMap::new());
+}
+
+fn cache_key(address: Address, block_num: u64) -> String {
+    format!("{:#x}_{}", address, &block_num)
+}
+
+pub fn is_balance_cached(address: Address, block_num: u64) -> bool {
+    CACHE
+        .lock()
+        .unwrap()
+        .contains_key(&cache_key(address, block_num))
+}
+
 pub async fn cached_get_balance(
-    web3: web3::Web3<web3::transports::Http>,
+    web3: ScanWeb3,
     address: Address,
     block_num: u64,
 ) -> Result<U256, WebPortalError> {
-    lazy_static! {
-        static ref CACHE: Mutex<HashMap<String, U256>> = Mutex::new(HashMap::new());
-    };
-
-    let key = format!("{:#x}_{}", address, &block_num);
+    let key = cache_key(address, block_num);
     let balance_from_cache = {
         let cache = CACHE.lock().unwrap();
         cache.get(&key).cloned()
@@ -23,13 +36,53 @@ pub async fn cached_get_balance(
     if let Some(balance) = balance_from_cache {
         Ok(balance)
     } else {
-        let balance = web3
-            .eth()
-            .balance(address, Some(BlockNumber::Number((block_num).into())))
-            .await
-            .map_err(|e| err_custom_create!("Error getting balance prev block: {}", e))?;
+        let balance = rpc_call("eth_getBalance", || {
+            web3.eth()
+                .balance(address, Some(BlockNumber::Number((block_num).into())))
+        })
+        .await
+        .map_err(|e| e.with_msg(format!("Error getting balance at block {}", block_num)))?;
         let mut cache = CACHE.lock().unwrap();
         cache.insert(key, balance);
         Ok(balance)
     }
 }
+
+/// Get balances at blocks not cached yet in JSON-RPC batches and put them into the cache
+pub async fn prefetch_balances(
+    web3: &ScanWeb3,
+    address: Address,
+    blocks: &[u64],
+) -> Result<(), WebPortalError> {
+    let missing: Vec<u64> = {
+        let cache = CACHE.lock().unwrap();
+        blocks
+            .iter()
+            .filter(|block_num| !cache.contains_key(&cache_key(address, **block_num)))
+            .cloned()
+            .collect()
+    };
+    if missing.is_empty() {
+        return Ok(());
+    }
+    log::debug!("Prefetching {} balances", missing.len());
+
+    let params = missing
+        .iter()
+        .map(|block_num| {
+            vec![
+                json!(address),
+                json!(BlockNumber::Number((*block_num).into())),
+            ]
+        })
+        .collect();
+    let balances: Vec<U256> = batch_call(web3, "eth_getBalance", params)
+        .await
+        .map_err(|e| e.with_msg("Error prefetching balances"))?;
+
+    let mut cache = CACHE.lock().unwrap();
+    for (block_num, balance) in missing.into_iter().zip(balances) {
+        cache.insert(cache_key(address, block_num), balance);
+    }
+    Ok(())
+}
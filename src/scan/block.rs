@@ -1,83 +1,161 @@
-use crate::db::model::transaction::{BlockDbObj, TxDbObj, TxTraceDbObj};
-use crate::db::ops::transaction::{delete_block_tx, insert_block, insert_tx, insert_tx_trace};
+use crate::db::model::transaction::{BlockCounterpartyDbObj, BlockDbObj, TxDbObj, TxTraceDbObj};
+use crate::db::ops::transaction::{
+    delete_block_tx, insert_block, insert_block_counterparty, insert_tx, insert_tx_trace,
+};
 use crate::err_custom_create;
 use crate::error::WebPortalError;
-use sqlx::SqlitePool;
+use crate::metrics;
+use crate::scan::rpc::rpc_call;
+use sqlx::SqliteConnection;
 use std::collections::HashMap;
 
 use crate::scan::balance::cached_get_balance;
+use crate::scan::capabilities::TraceMode;
+use crate::scan::trace::cached_get_block_traces;
+use crate::scan::transport::ScanWeb3;
 use std::str::FromStr;
-use web3::types::{Action, Address, BlockId, BlockNumber, U256};
+use web3::types::{Address, BlockId, BlockNumber, U256};
 
+/// Sum of withdrawals to the address in wei
+pub fn sum_withdrawals(
+    withdrawals: Option<&serde_json::Value>,
+    address: Address,
+) -> Result<i128, WebPortalError> {
+    // "address": String("0x03e543052f41799de45d97f801f61688240ae7c1"),
+    // "amount": String("0x12475fe"),
+    // "index": String("0x39d661b"),
+    // "validatorIndex": String("0x150cda")
+
+    // blocks before Shanghai have no withdrawals at all
+    let Some(withdrawals) = withdrawals else {
+        return Ok(0);
+    };
+    let withdrawals = withdrawals.as_array().ok_or(err_custom_create!(
+        "Withdrawals are not a list: {}",
+        withdrawals
+    ))?;
+
+    let mut amount_withdrawn = 0_i128;
+    for withdrawal in withdrawals {
+        let field = |name: &str| {
+            withdrawal
+                .get(name)
+                .and_then(|v| v.as_str())
+                .ok_or(err_custom_create!(
+                    "Withdrawal without {}: {}",
+                    name,
+                    withdrawal
+                ))
+        };
+        let amount = U256::from_str(field("amount")?)
+            .map_err(|e| err_custom_create!("Invalid withdrawal amount: {}", e))?;
+        let withdrawal_address = Address::from_str(field("address")?)
+            .map_err(|e| err_custom_create!("Invalid withdrawal address: {}", e))?;
+        if withdrawal_address == address {
+            log::info!("Found withdrawal: {}", withdrawal);
+            // amount is given in gwei, so normalize it
+            amount_withdrawn += amount.as_u128() as i128 * 1000000000i128;
+        }
+    }
+    Ok(amount_withdrawn)
+}
+
+/// Block with transactions and traces touching the address, ready to be saved
+#[derive(Debug, Clone)]
+pub struct InspectedBlock {
+    pub block: BlockDbObj,
+    pub txs: Vec<TxDbObj>,
+    pub traces: Vec<TxTraceDbObj>,
+    /// Value moved from and to every counterparty
+    pub counterparties: Vec<BlockCounterpartyDbObj>,
+}
+
+/// Replace stored rows of the block with the inspection result.
+/// Pass a transaction, so the block is never left half written.
+pub async fn save_inspected_block(
+    conn: &mut SqliteConnection,
+    inspected: &InspectedBlock,
+) -> Result<(), WebPortalError> {
+    delete_block_tx(
+        &mut *conn,
+        inspected.block.address.clone(),
+        inspected.block.block_number,
+    )
+    .await
+    .map_err(|e| err_custom_create!("Error deleting block tx: {}", e))?;
+
+    insert_block(&mut *conn, &inspected.block)
+        .await
+        .map_err(|e| err_custom_create!("Error inserting block: {}", e))?;
+
+    for tx in &inspected.txs {
+        insert_tx(&mut *conn, tx)
+            .await
+            .map_err(|e| err_custom_create!("Error inserting tx: {}", e))?;
+    }
+    for trace in &inspected.traces {
+        insert_tx_trace(&mut *conn, trace)
+            .await
+            .map_err(|e| err_custom_create!("Error inserting tx trace: {}", e))?;
+    }
+    for counterparty in &inspected.counterparties {
+        insert_block_counterparty(&mut *conn, counterparty)
+            .await
+            .map_err(|e| err_custom_create!("Error inserting block counterparty: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Inspect block for transfers explaining the balance change of the address.
+/// Returns None when the balance did not change in this block.
 pub async fn inspect_block(
-    web3: web3::Web3<web3::transports::Http>,
-    db: SqlitePool,
+    web3: ScanWeb3,
     address: Address,
     block_num: u64,
-) -> Result<(), WebPortalError> {
+    trace_mode: TraceMode,
+) -> Result<Option<InspectedBlock>, WebPortalError> {
     log::info!("This block: {}", block_num);
     let balance_prev = cached_get_balance(web3.clone(), address, block_num - 1)
         .await
-        .map_err(|e| err_custom_create!("Error getting balance prev block: {}", e))?;
+        .map_err(|e| e.with_msg("Error getting balance prev block"))?;
     let balance_curr = cached_get_balance(web3.clone(), address, block_num)
         .await
-        .map_err(|e| err_custom_create!("Error getting balance prev block: {}", e))?;
+        .map_err(|e| e.with_msg("Error getting balance current block"))?;
 
     let balance_diff = balance_curr.as_u128() as i128 - balance_prev.as_u128() as i128;
     if balance_diff == 0 {
         log::info!("Balance Diff is 0 for block {}", block_num);
-        return Ok(());
+        return Ok(None);
     }
     log::info!("Balance Diff: {}", balance_diff);
-    let block = web3
-        .eth()
-        .block_with_txs(BlockId::Number(BlockNumber::Number(block_num.into())))
-        .await
-        .map_err(|e| err_custom_create!("Error getting block: {} {}", block_num, e))?
-        .ok_or(err_custom_create!("Block info not found {}", block_num))?;
-
-    // "address": String("0x03e543052f41799de45d97f801f61688240ae7c1"),
-    // "amount": String("0x12475fe"),
-    // "index": String("0x39d661b"),
-    // "validatorIndex": String("0x150cda")
-
-    let mut amount_withdrawn = 0_i128;
-    let withdrawals = block.withdrawals.unwrap().as_array().unwrap().clone();
+    let block = rpc_call("eth_getBlockByNumber", || {
+        web3.eth()
+            .block_with_txs(BlockId::Number(BlockNumber::Number(block_num.into())))
+    })
+    .await
+    .map_err(|e| e.with_msg(format!("Error getting block {}", block_num)))?
+    .ok_or(err_custom_create!("Block info not found {}", block_num))?;
 
-    for withdrawal in withdrawals {
-        let withdrawal_obj = withdrawal.as_object().unwrap();
-        let amount = withdrawal_obj.get("amount").unwrap().as_str().unwrap();
-        let withdrawal_address = withdrawal_obj.get("address").unwrap().as_str().unwrap();
-        let _index = withdrawal_obj.get("index").unwrap().as_str().unwrap();
-        let _validator_index = withdrawal_obj
-            .get("validatorIndex")
-            .unwrap()
-            .as_str()
-            .unwrap();
-        if Address::from_str(withdrawal_address).unwrap() == address {
-            log::info!("Found withdrawal: {}", withdrawal);
-            // amount is given in gwei, so normalize it
-            amount_withdrawn += U256::from_str(amount).unwrap().as_u128() as i128 * 1000000000i128;
-        }
-    }
+    let amount_withdrawn = sum_withdrawals(block.withdrawals.as_ref(), address)?;
 
     let mut from_txs: HashMap<Address, U256> = HashMap::new();
     let mut to_txs: HashMap<Address, U256> = HashMap::new();
 
-    delete_block_tx(&db, format!("{:#x}", address), block_num as i64)
-        .await
-        .map_err(|e| err_custom_create!("Error deleting block tx: {}", e))?;
-
     let mut interesting_txs = Vec::new();
     let mut interesting_traces = Vec::new();
     let mut miner_reward = 0i128;
 
+    let block_traces = cached_get_block_traces(
+        web3.clone(),
+        block_num,
+        &block.transactions,
+        address,
+        trace_mode,
+    )
+    .await?;
+
     for (block_index, tx) in block.transactions.into_iter().enumerate() {
-        let traces = web3
-            .trace()
-            .transaction(tx.hash)
-            .await
-            .map_err(|e| err_custom_create!("Error getting traces: {}", e))?;
+        let calls = block_traces.get(&block_index).cloned().unwrap_or_default();
 
         let tx_obj = TxDbObj {
             address: format!("{:#x}", address),
@@ -89,45 +167,34 @@ pub async fn inspect_block(
 
         let mut tx_interesting = false;
         let mut traces2 = Vec::new();
-        for (trace_idx, trace) in traces.into_iter().enumerate() {
-            match trace.action {
-                Action::Call(call) => {
-                    let new_db_part = TxTraceDbObj {
-                        address: format!("{:#x}", address),
-                        tx_hash: format!("{:#x}", tx.hash),
-                        block_number: block_num as i64,
-                        block_index: block_index as i64,
-                        trace_index: trace_idx as i64,
-                        from_addr: format!("{:#x}", call.from),
-                        to_addr: format!("{:#x}", call.to),
-                        value: call.value.to_string(),
-                        gas_used: call.gas.to_string(),
-                    };
-                    traces2.push(new_db_part);
-
-                    if call.from == address || call.to == address {
-                        log::info!("Found transaction: {:?}", tx);
-                        tx_interesting = true;
-                    }
-
-                    if call.from == block.author && call.to == address {
-                        log::info!("Found mev reward: {:?}", tx);
-                        miner_reward += call.value.as_u128() as i128;
-                    } else if call.to == address {
-                        let value = to_txs.entry(call.from).or_insert(U256::from(0));
-                        *value += call.value;
-                    } else if call.from == address {
-                        let value = from_txs.entry(call.to).or_insert(U256::from(0));
-                        *value += call.value;
-                    }
-                }
-                Action::Reward(_) => {
-                    return Err(err_custom_create!("Reward action found"));
-                }
-                _ => {
-                    log::debug!("Unknown action: {:?}", trace.action);
-                    continue;
-                }
+        for (trace_idx, call) in calls.into_iter().enumerate() {
+            let new_db_part = TxTraceDbObj {
+                address: format!("{:#x}", address),
+                tx_hash: format!("{:#x}", tx.hash),
+                block_number: block_num as i64,
+                block_index: block_index as i64,
+                trace_index: trace_idx as i64,
+                from_addr: format!("{:#x}", call.from),
+                to_addr: format!("{:#x}", call.to),
+                value: call.value.to_string(),
+                gas_used: call.gas.to_string(),
+            };
+            traces2.push(new_db_part);
+
+            if call.from == address || call.to == address {
+                log::info!("Found transaction: {:?}", tx);
+                tx_interesting = true;
+            }
+
+            if call.from == block.author && call.to == address {
+                log::info!("Found mev reward: {:?}", tx);
+                miner_reward += call.value.as_u128() as i128;
+            } else if call.to == address {
+                let value = to_txs.entry(call.from).or_insert(U256::from(0));
+                *value += call.value;
+            } else if call.from == address {
+                let value = from_txs.entry(call.to).or_insert(U256::from(0));
+                *value += call.value;
             }
         }
         if tx_interesting {
@@ -166,43 +233,95 @@ pub async fn inspect_block(
         );
     }
 
-    let mev_reward = miner_reward;
-    insert_block(
-        &db,
-        &BlockDbObj {
-            address: format!("{:#x}", address),
-            block_number: block_num as i64,
-            timestamp: chrono::DateTime::from_timestamp(block.timestamp.as_u64() as i64, 0)
-                .unwrap(),
-            balance: balance_curr.to_string(),
-            balance_diff: balance_diff.to_string(),
-            updated: chrono::Utc::now(),
-            block_miner: format!("{:#x}", block.author),
-            consensus_reward: amount_withdrawn.to_string(),
-            mev_reward: mev_reward.to_string(),
-            block_reward: U256::zero().to_string(),
-            amount_incoming: sum_to_txs.to_string(),
-            amount_outgoing: sum_from_txs.to_string(),
-        },
-    )
-    .await
-    .map_err(|e| err_custom_create!("Error inserting block: {}", e))?;
-
-    for tx in interesting_txs {
-        insert_tx(&db, &tx)
-            .await
-            .map_err(|e| err_custom_create!("Error inserting tx: {}", e))?;
+    let mut flows: HashMap<Address, (U256, U256)> = HashMap::new();
+    for (counterparty, value) in &to_txs {
+        flows.entry(*counterparty).or_default().0 += *value;
     }
-    for trace in interesting_traces {
-        insert_tx_trace(&db, &trace)
-            .await
-            .map_err(|e| err_custom_create!("Error inserting tx trace: {}", e))?;
+    for (counterparty, value) in &from_txs {
+        flows.entry(*counterparty).or_default().1 += *value;
     }
+    // zero value calls (token transfers, contract calls) did not move any ETH
+    let counterparties = flows
+        .into_iter()
+        .filter(|(_, (amount_in, amount_out))| !amount_in.is_zero() || !amount_out.is_zero())
+        .map(
+            |(counterparty, (amount_in, amount_out))| BlockCounterpartyDbObj {
+                address: format!("{:#x}", address),
+                block_number: block_num as i64,
+                counterparty: format!("{:#x}", counterparty),
+                amount_in: amount_in.to_string(),
+                amount_out: amount_out.to_string(),
+            },
+        )
+        .collect();
 
-    if sum_diff + amount_withdrawn + mev_reward != balance_diff {
-        log::error!("Sum diff does not match {} != {}", sum_diff, balance_diff);
-        return Err(err_custom_create!("Sum diff does not match"));
+    let mev_reward = miner_reward;
+    let unexplained_value = balance_diff - (sum_diff + amount_withdrawn + mev_reward);
+    let mut reconciled = true;
+    if unexplained_value != 0 {
+        if trace_mode == TraceMode::ReceiptOnly {
+            metrics::inc_anomaly("unexplained_value");
+            log::warn!(
+                "Block {} has unexplained internal value {}",
+                block_num,
+                unexplained_value
+            );
+        } else {
+            metrics::inc_anomaly("unreconciled");
+            log::error!("Sum diff does not match {} != {}", sum_diff, balance_diff);
+            reconciled = false;
+        }
     }
 
-    Ok(())
+    let block = BlockDbObj {
+        address: format!("{:#x}", address),
+        block_number: block_num as i64,
+        timestamp: chrono::DateTime::from_timestamp(block.timestamp.as_u64() as i64, 0).unwrap(),
+        balance: balance_curr.to_string(),
+        balance_diff: balance_diff.to_string(),
+        updated: chrono::Utc::now(),
+        block_miner: format!("{:#x}", block.author),
+        consensus_reward: amount_withdrawn.to_string(),
+        mev_reward: mev_reward.to_string(),
+        block_reward: U256::zero().to_string(),
+        amount_incoming: sum_to_txs.to_string(),
+        amount_outgoing: sum_from_txs.to_string(),
+        trace_mode: trace_mode.to_string(),
+        unexplained_value: (unexplained_value != 0).then(|| unexplained_value.to_string()),
+        reconciled,
+    };
+
+    Ok(Some(InspectedBlock {
+        block,
+        txs: interesting_txs,
+        traces: interesting_traces,
+        counterparties,
+    }))
+}
+
+#[tokio::test]
+async fn inspect_block_replay_test() {
+    use crate::scan::transport::ScanTransport;
+    // written by hand in the SCANNER_RPC_RECORD format, block with a single withdrawal to the address
+    let transport = ScanTransport::replay(std::path::Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/rpc/withdrawal_block.ndjson"
+    )))
+    .unwrap();
+    let address = Address::from_str("0x03e543052f41799de45d97f801f61688240ae7c1").unwrap();
+
+    let inspected = inspect_block(
+        web3::Web3::new(transport),
+        address,
+        20000001,
+        TraceMode::TraceTransaction,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert!(inspected.block.reconciled);
+    assert_eq!(inspected.block.balance_diff, "19166718000000000");
+    assert_eq!(inspected.block.consensus_reward, "19166718000000000");
+    assert!(inspected.txs.is_empty());
 }
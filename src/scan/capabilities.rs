@@ -0,0 +1,205 @@
+use crate::err_custom_create;
+use crate::error::{ErrorBag, WebPortalError};
+use crate::scan::discovery::DiscoveryStrategy;
+use crate::scan::rpc::rpc_call;
+use crate::scan::run::ScanOptions;
+use crate::scan::transport::ScanWeb3;
+use serde_json::json;
+use std::fmt::Display;
+use web3::types::{Address, BlockId, BlockNumber, TraceFilterBuilder};
+use web3::Transport;
+
+/// How the scanner gets internal calls of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceMode {
+    /// trace_block for the whole block (Parity/Erigon)
+    TraceBlock,
+    /// trace_transaction for every transaction
+    TraceTransaction,
+    /// debug_traceTransaction with callTracer (Geth)
+    DebugTrace,
+    /// Only top level transfers from receipts, internal value is not explained
+    ReceiptOnly,
+}
+
+impl Display for TraceMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceMode::TraceBlock => write!(f, "trace_block"),
+            TraceMode::TraceTransaction => write!(f, "trace_transaction"),
+            TraceMode::DebugTrace => write!(f, "debug_trace"),
+            TraceMode::ReceiptOnly => write!(f, "receipt_only"),
+        }
+    }
+}
+
+/// How far back from the chain head a block with transactions is looked for
+const PROBE_MAX_BLOCKS_BACK: u64 = 1000;
+
+/// RPC methods supported by the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCapabilities {
+    pub trace_block: bool,
+    pub trace_transaction: bool,
+    pub trace_filter: bool,
+    pub debug_trace: bool,
+}
+
+/// Check if the error means that node does not serve the method at all
+fn is_unsupported<T>(res: &Result<T, WebPortalError>) -> bool {
+    match res {
+        Ok(_) => false,
+        Err(WebPortalError {
+            inner: ErrorBag::Web3Error(web3::Error::Rpc(e)),
+            ..
+        }) => {
+            let message = e.message.to_lowercase();
+            e.code.code() == -32601
+                || message.contains("not supported")
+                || message.contains("does not exist")
+                || message.contains("not available")
+                || message.contains("method not found")
+        }
+        // any other error (for example unknown transaction) means the method is there
+        Err(_) => false,
+    }
+}
+
+impl NodeCapabilities {
+    /// Probe trace and debug methods once, using a recent block.
+    /// Without any recent transaction only the block level methods can be probed.
+    pub async fn detect(web3: &ScanWeb3) -> Result<NodeCapabilities, WebPortalError> {
+        let head = rpc_call("eth_blockNumber", || web3.eth().block_number())
+            .await
+            .map_err(|e| e.with_msg("Error getting current block number"))?
+            .as_u64();
+        // probe with a real transaction, nodes may answer unknown hashes before checking the method
+        let probe_start = head.saturating_sub(10);
+        let mut probe_block = probe_start;
+        let probe_tx = loop {
+            let block = rpc_call("eth_getBlockByNumber", || {
+                web3.eth()
+                    .block(BlockId::Number(BlockNumber::Number(probe_block.into())))
+            })
+            .await
+            .map_err(|e| e.with_msg(format!("Error getting block {}", probe_block)))?
+            .ok_or(err_custom_create!("Block info not found {}", probe_block))?;
+            if let Some(tx) = block.transactions.first() {
+                break Some(*tx);
+            }
+            if probe_block == 0 || head - probe_block >= PROBE_MAX_BLOCKS_BACK {
+                log::warn!(
+                    "No transaction found in blocks {}..={}, transaction traces are not probed",
+                    probe_block,
+                    probe_start
+                );
+                probe_block = probe_start;
+                break None;
+            }
+            probe_block -= 1;
+        };
+
+        let trace_block = rpc_call("trace_block", || {
+            web3.trace().block(BlockNumber::Number(probe_block.into()))
+        })
+        .await;
+        let probe_filter = TraceFilterBuilder::default()
+            .from_block(BlockNumber::Number(probe_block.into()))
+            .to_block(BlockNumber::Number(probe_block.into()))
+            .to_address(vec![Address::zero()])
+            .build();
+        let trace_filter =
+            rpc_call("trace_filter", || web3.trace().filter(probe_filter.clone())).await;
+        let (trace_transaction, debug_trace) = match probe_tx {
+            Some(probe_tx) => {
+                let trace_transaction =
+                    rpc_call("trace_transaction", || web3.trace().transaction(probe_tx)).await;
+                let debug_trace = rpc_call("debug_traceTransaction", || {
+                    web3.transport().execute(
+                        "debug_traceTransaction",
+                        vec![json!(probe_tx), json!({"tracer": "callTracer"})],
+                    )
+                })
+                .await;
+                (
+                    !is_unsupported(&trace_transaction),
+                    !is_unsupported(&debug_trace),
+                )
+            }
+            None => (false, false),
+        };
+
+        let capabilities = NodeCapabilities {
+            trace_block: !is_unsupported(&trace_block),
+            trace_transaction,
+            trace_filter: !is_unsupported(&trace_filter),
+            debug_trace,
+        };
+        log::info!("Detected node capabilities: {:?}", capabilities);
+        Ok(capabilities)
+    }
+
+    /// Best trace mode the node supports
+    pub fn trace_mode(&self) -> TraceMode {
+        if self.trace_block {
+            TraceMode::TraceBlock
+        } else if self.trace_transaction {
+            TraceMode::TraceTransaction
+        } else if self.debug_trace {
+            TraceMode::DebugTrace
+        } else {
+            log::warn!("Node supports no trace method, internal calls are not explained");
+            TraceMode::ReceiptOnly
+        }
+    }
+}
+
+/// Fill in trace mode and fix discovery strategy according to what the node supports
+pub async fn resolve_scan_options(
+    web3: &ScanWeb3,
+    options: ScanOptions,
+) -> Result<ScanOptions, WebPortalError> {
+    if options.trace_mode.is_some() && options.discovery != DiscoveryStrategy::TraceFilter {
+        return Ok(options);
+    }
+    let capabilities = NodeCapabilities::detect(web3).await?;
+    let mut options = options;
+    if options.trace_mode.is_none() {
+        options.trace_mode = Some(capabilities.trace_mode());
+    }
+    if options.discovery == DiscoveryStrategy::TraceFilter && !capabilities.trace_filter {
+        log::warn!("Node does not support trace_filter, using balance probe discovery");
+        options.discovery = DiscoveryStrategy::BalanceProbe;
+    }
+    log::info!(
+        "Scanning with trace mode {} and {:?} discovery",
+        options.trace_mode.unwrap(),
+        options.discovery
+    );
+    Ok(options)
+}
+
+#[tokio::test]
+async fn detect_without_transactions_test() {
+    use crate::scan::transport::ScanTransport;
+    // written by hand, empty blocks of a node serving trace_block but not trace_filter
+    let transport = ScanTransport::replay(std::path::Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/rpc/no_transactions.ndjson"
+    )))
+    .unwrap();
+
+    let capabilities = NodeCapabilities::detect(&web3::Web3::new(transport))
+        .await
+        .unwrap();
+    assert_eq!(
+        capabilities,
+        NodeCapabilities {
+            trace_block: true,
+            trace_transaction: false,
+            trace_filter: false,
+            debug_trace: false,
+        }
+    );
+    assert_eq!(capabilities.trace_mode(), TraceMode::TraceBlock);
+}
@@ -1,7 +1,13 @@
 use crate::db::ops::transaction::delete_scan;
 use crate::err_custom_create;
 use crate::error::WebPortalError;
-use crate::scan::run::scan_address;
+use crate::metrics::metrics_server;
+use crate::scan::capabilities::{NodeCapabilities, TraceMode};
+use crate::scan::coverage::{fill_gap, get_coverage_report};
+use crate::scan::discovery::DiscoveryStrategy;
+use crate::scan::finality::FinalityPolicy;
+use crate::scan::follow::follow_address;
+use crate::scan::run::{create_web3, scan_address, ScanOptions};
 use clap::Parser;
 use sqlx::SqlitePool;
 use web3::types::Address;
@@ -16,6 +22,22 @@ pub struct ScanCommand {
     block_end: Option<u64>,
     #[arg(long)]
     remove_prev_scan: bool,
+    /// Scan boundary: finalized, safe or depth:<blocks> behind the chain head
+    #[arg(long, default_value_t = FinalityPolicy::default())]
+    finality: FinalityPolicy,
+    /// How blocks worth inspecting are found
+    #[arg(long, value_enum, default_value_t = DiscoveryStrategy::default())]
+    discovery: DiscoveryStrategy,
+    /// How internal calls are fetched, detected from node capabilities when not given
+    #[arg(long, value_enum)]
+    trace_mode: Option<TraceMode>,
+    /// Keep following the chain head after reaching it
+    #[arg(long, conflicts_with = "block_end")]
+    follow: bool,
+    /// Serve scanner and RPC metrics on /metrics at this address while scanning,
+    /// with the same access rules as the server
+    #[arg(long)]
+    metrics_addr: Option<String>,
 }
 
 pub async fn scan_command(
@@ -27,8 +49,27 @@ pub async fn scan_command(
         block_start,
         block_end,
         remove_prev_scan,
+        finality,
+        discovery,
+        trace_mode,
+        follow,
+        metrics_addr,
     } = scan_command;
 
+    if let Some(metrics_addr) = metrics_addr {
+        let server = metrics_server(&metrics_addr)
+            .map_err(|e| err_custom_create!("Error serving metrics on {}: {}", metrics_addr, e))?;
+        actix_web::rt::spawn(server);
+    }
+
+    let options = ScanOptions {
+        finality,
+        discovery,
+        trace_mode,
+        existing_only: false,
+        follow,
+    };
+
     if remove_prev_scan {
         log::warn!("Deleting scan for address: {}", address);
 
@@ -40,7 +81,80 @@ pub async fn scan_command(
             })?;
     }
 
-    scan_address(conn.clone(), address, block_start, block_end).await?;
+    if follow {
+        follow_address(conn.clone(), address, block_start, options).await?;
+    } else {
+        scan_address(conn.clone(), address, block_start, block_end, options).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct CoverageCommand {
+    #[arg(long)]
+    address: Address,
+    /// Inspect all blocks of the uninspected ranges
+    #[arg(long)]
+    fill: bool,
+    /// How internal calls are fetched when filling, detected from node capabilities when not given
+    #[arg(long, value_enum)]
+    trace_mode: Option<TraceMode>,
+}
+
+pub async fn coverage_command(
+    conn: SqlitePool,
+    coverage_command: CoverageCommand,
+) -> Result<(), WebPortalError> {
+    let CoverageCommand {
+        address,
+        fill,
+        trace_mode,
+    } = coverage_command;
+
+    let report = get_coverage_report(&conn, &format!("{address:#x}"))
+        .await?
+        .ok_or(err_custom_create!("Scan not found for {address:#x}"))?;
+
+    println!(
+        "Scanned blocks {}..{}",
+        report.scan.first_block_number, report.scan.next_block_number
+    );
+    for range in &report.ranges {
+        println!(
+            "  {}..{} {}",
+            range.block_start, range.block_end, range.status
+        );
+    }
+    println!("Uninspected ranges: {}", report.gaps.len());
+    for gap in &report.gaps {
+        println!(
+            "  {}..{} {}",
+            gap.block_start,
+            gap.block_end,
+            gap.status.as_deref().unwrap_or("not recorded")
+        );
+    }
+
+    if fill {
+        let web3 = create_web3()?;
+        // detected once for all gaps
+        let trace_mode = match trace_mode {
+            Some(trace_mode) => trace_mode,
+            None => NodeCapabilities::detect(&web3).await?.trace_mode(),
+        };
+        for gap in report.gaps {
+            fill_gap(
+                web3.clone(),
+                conn.clone(),
+                address,
+                gap.block_start,
+                gap.block_end,
+                Some(trace_mode),
+            )
+            .await?;
+        }
+    }
 
     Ok(())
 }
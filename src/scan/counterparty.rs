@@ -0,0 +1,89 @@
+use crate::db::model::transaction::{wei, BlockCounterpartyDbObj};
+use crate::db::ops::transaction::{get_block_counterparties, BlockFilter};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+/// Value moved between the address and a counterparty over a period, wei amounts as strings
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterpartyFlow {
+    pub counterparty: String,
+    pub amount_in: String,
+    pub amount_out: String,
+    /// Blocks with transfers between the two
+    pub blocks: usize,
+}
+
+/// Sum flows per counterparty, largest total of in and out first
+fn rank_counterparties(rows: Vec<BlockCounterpartyDbObj>, limit: usize) -> Vec<CounterpartyFlow> {
+    let mut sums: HashMap<String, (i128, i128, usize)> = HashMap::new();
+    for row in rows {
+        let sum = sums.entry(row.counterparty).or_default();
+        sum.0 += wei(&row.amount_in);
+        sum.1 += wei(&row.amount_out);
+        sum.2 += 1;
+    }
+    let mut ranked: Vec<_> = sums.into_iter().collect();
+    ranked.sort_by(|(a, (a_in, a_out, _)), (b, (b_in, b_out, _))| {
+        (b_in + b_out).cmp(&(a_in + a_out)).then_with(|| a.cmp(b))
+    });
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(
+            |(counterparty, (amount_in, amount_out, blocks))| CounterpartyFlow {
+                counterparty,
+                amount_in: amount_in.to_string(),
+                amount_out: amount_out.to_string(),
+                blocks,
+            },
+        )
+        .collect()
+}
+
+pub async fn get_counterparties(
+    conn: &SqlitePool,
+    address: &str,
+    filter: &BlockFilter,
+    limit: usize,
+) -> Result<Vec<CounterpartyFlow>, WebPortalError> {
+    let rows = get_block_counterparties(conn, address, filter)
+        .await
+        .map_err(|e| err_custom_create!("Error getting counterparties: {}", e))?;
+    Ok(rank_counterparties(rows, limit))
+}
+
+#[test]
+fn rank_counterparties_test() {
+    let row = |block_number, counterparty: &str, amount_in: u128, amount_out: u128| {
+        BlockCounterpartyDbObj {
+            address: "0x01".to_string(),
+            block_number,
+            counterparty: counterparty.to_string(),
+            amount_in: amount_in.to_string(),
+            amount_out: amount_out.to_string(),
+        }
+    };
+    let rows = vec![
+        row(1, "0xexchange", 100, 0),
+        row(2, "0xpool", 0, 30),
+        row(3, "0xexchange", 0, 50),
+        row(4, "0xfriend", 1, 0),
+    ];
+
+    let ranked = rank_counterparties(rows, 2);
+    assert_eq!(ranked.len(), 2);
+    assert_eq!(
+        ranked[0],
+        CounterpartyFlow {
+            counterparty: "0xexchange".to_string(),
+            amount_in: "100".to_string(),
+            amount_out: "50".to_string(),
+            blocks: 2,
+        }
+    );
+    assert_eq!(ranked[1].counterparty, "0xpool");
+}
@@ -0,0 +1,306 @@
+use crate::db::model::transaction::{CoverageRangeDbObj, ScanDbObj};
+use crate::db::ops::transaction::{
+    delete_coverage_range, get_coverage_ranges, get_coverage_ranges_touching, get_scan,
+    insert_coverage_range,
+};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::capabilities::{NodeCapabilities, TraceMode};
+use crate::scan::failed::inspect_and_save_block;
+use crate::scan::transport::ScanWeb3;
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::fmt::Display;
+use web3::types::Address;
+
+/// How the scanner handled blocks of a coverage range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageStatus {
+    /// Every block was inspected
+    Inspected,
+    /// Skipped, because the balance did not change (or was explained by trace_filter hits)
+    SkippedUnchanged,
+    /// Inspection failed, blocks are in the retry queue
+    Failed,
+}
+
+impl Display for CoverageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoverageStatus::Inspected => write!(f, "inspected"),
+            CoverageStatus::SkippedUnchanged => write!(f, "skipped_unchanged"),
+            CoverageStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Blocks [block_start, block_end) of the scan that were not inspected
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageGap {
+    pub block_start: u64,
+    pub block_end: u64,
+    /// skipped_unchanged or failed, None when nothing was recorded (scanned before coverage tracking)
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageReport {
+    pub scan: ScanDbObj,
+    pub ranges: Vec<CoverageRangeDbObj>,
+    pub gaps: Vec<CoverageGap>,
+}
+
+/// Put new range over ranges touching it. Parts of ranges with other status outside
+/// the new range are kept, ranges with the same status are merged into it.
+fn merge_coverage(
+    touching: Vec<CoverageRangeDbObj>,
+    new: CoverageRangeDbObj,
+) -> Vec<CoverageRangeDbObj> {
+    let mut merged = new;
+    let mut result = Vec::new();
+    for range in touching {
+        if range.status == merged.status {
+            merged.block_start = std::cmp::min(merged.block_start, range.block_start);
+            merged.block_end = std::cmp::max(merged.block_end, range.block_end);
+            continue;
+        }
+        if range.block_start < merged.block_start {
+            result.push(CoverageRangeDbObj {
+                block_end: std::cmp::min(range.block_end, merged.block_start),
+                ..range.clone()
+            });
+        }
+        if range.block_end > merged.block_end {
+            result.push(CoverageRangeDbObj {
+                block_start: std::cmp::max(range.block_start, merged.block_end),
+                ..range
+            });
+        }
+    }
+    result.push(merged);
+    result.sort_by_key(|range| range.block_start);
+    result
+}
+
+/// Record status of blocks [block_start, block_end).
+/// Pass a transaction, ranges are replaced with a few statements.
+pub async fn record_coverage(
+    conn: &mut SqliteConnection,
+    address: &str,
+    block_start: u64,
+    block_end: u64,
+    status: CoverageStatus,
+) -> Result<(), WebPortalError> {
+    if block_start >= block_end {
+        return Ok(());
+    }
+    let touching =
+        get_coverage_ranges_touching(&mut *conn, address, block_start as i64, block_end as i64)
+            .await
+            .map_err(|e| err_custom_create!("Error getting coverage: {}", e))?;
+    for range in &touching {
+        delete_coverage_range(&mut *conn, address, range.block_start)
+            .await
+            .map_err(|e| err_custom_create!("Error deleting coverage range: {}", e))?;
+    }
+    let new = CoverageRangeDbObj {
+        address: address.to_string(),
+        block_start: block_start as i64,
+        block_end: block_end as i64,
+        status: status.to_string(),
+        updated: chrono::Utc::now(),
+    };
+    for range in merge_coverage(touching, new) {
+        insert_coverage_range(&mut *conn, &range)
+            .await
+            .map_err(|e| err_custom_create!("Error inserting coverage range: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Record ranges skipped without inspection
+pub async fn save_skipped_ranges(
+    db: &SqlitePool,
+    address: Address,
+    ranges: &[(u64, u64)],
+) -> Result<(), WebPortalError> {
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    let address = format!("{:#x}", address);
+    let mut db_tx = db
+        .begin()
+        .await
+        .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+    for (block_start, block_end) in ranges {
+        record_coverage(
+            &mut db_tx,
+            &address,
+            *block_start,
+            *block_end,
+            CoverageStatus::SkippedUnchanged,
+        )
+        .await?;
+    }
+    db_tx
+        .commit()
+        .await
+        .map_err(|e| err_custom_create!("Error committing coverage: {}", e))?;
+    Ok(())
+}
+
+/// Parts of [block_start, block_end) not covered by inspected ranges
+pub fn find_gaps(
+    ranges: &[CoverageRangeDbObj],
+    block_start: u64,
+    block_end: u64,
+) -> Vec<CoverageGap> {
+    let mut gaps = Vec::new();
+    let mut pos = block_start;
+    for range in ranges {
+        let range_start = std::cmp::max(range.block_start as u64, block_start);
+        let range_end = std::cmp::min(range.block_end as u64, block_end);
+        if range_start >= range_end {
+            continue;
+        }
+        if range_start > pos {
+            gaps.push(CoverageGap {
+                block_start: pos,
+                block_end: range_start,
+                status: None,
+            });
+        }
+        if range.status != CoverageStatus::Inspected.to_string() {
+            gaps.push(CoverageGap {
+                block_start: range_start,
+                block_end: range_end,
+                status: Some(range.status.clone()),
+            });
+        }
+        pos = std::cmp::max(pos, range_end);
+    }
+    if pos < block_end {
+        gaps.push(CoverageGap {
+            block_start: pos,
+            block_end,
+            status: None,
+        });
+    }
+    gaps
+}
+
+/// Coverage ranges and gaps between first_block_number and next_block_number,
+/// None when the address was never scanned
+pub async fn get_coverage_report(
+    db: &SqlitePool,
+    address: &str,
+) -> Result<Option<CoverageReport>, WebPortalError> {
+    let Some(scan) = get_scan(db, address)
+        .await
+        .map_err(|e| err_custom_create!("Error getting scan: {}", e))?
+    else {
+        return Ok(None);
+    };
+    let ranges = get_coverage_ranges(db, address)
+        .await
+        .map_err(|e| err_custom_create!("Error getting coverage: {}", e))?;
+    let gaps = find_gaps(
+        &ranges,
+        scan.first_block_number as u64,
+        scan.next_block_number as u64,
+    );
+    Ok(Some(CoverageReport { scan, ranges, gaps }))
+}
+
+/// Inspect every block of [block_start, block_end), which must be inside the scanned range.
+/// Trace mode is detected from node capabilities when not given.
+pub async fn fill_gap(
+    web3: ScanWeb3,
+    db: SqlitePool,
+    address: Address,
+    block_start: u64,
+    block_end: u64,
+    trace_mode: Option<TraceMode>,
+) -> Result<(), WebPortalError> {
+    let scan = get_scan(&db, &format!("{:#x}", address))
+        .await
+        .map_err(|e| err_custom_create!("Error getting scan: {}", e))?
+        .ok_or(err_custom_create!("Scan not found for {:#x}", address))?;
+    if block_start >= block_end
+        || block_start < scan.first_block_number as u64
+        || block_end > scan.next_block_number as u64
+    {
+        return Err(err_custom_create!(
+            "Range {}..{} is outside of scanned blocks {}..{}",
+            block_start,
+            block_end,
+            scan.first_block_number,
+            scan.next_block_number
+        ));
+    }
+    let trace_mode = match trace_mode {
+        Some(trace_mode) => trace_mode,
+        None => NodeCapabilities::detect(&web3).await?.trace_mode(),
+    };
+
+    log::info!("Filling coverage gap {}..{}", block_start, block_end);
+    for block_num in block_start..block_end {
+        inspect_and_save_block(&web3, &db, address, block_num, trace_mode).await?;
+    }
+    log::info!("Coverage gap {}..{} filled", block_start, block_end);
+    Ok(())
+}
+
+#[test]
+fn merge_coverage_test() {
+    let now = chrono::Utc::now();
+    let range = |block_start: i64, block_end: i64, status: CoverageStatus| CoverageRangeDbObj {
+        address: "0x0000000000000000000000000000000000000001".to_string(),
+        block_start,
+        block_end,
+        status: status.to_string(),
+        updated: now,
+    };
+    let bounds = |ranges: &[CoverageRangeDbObj]| {
+        ranges
+            .iter()
+            .map(|r| (r.block_start, r.block_end, r.status.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    // adjacent ranges with the same status are merged
+    let merged = merge_coverage(
+        vec![range(0, 10, CoverageStatus::Inspected)],
+        range(10, 11, CoverageStatus::Inspected),
+    );
+    assert_eq!(bounds(&merged), vec![(0, 11, "inspected".to_string())]);
+
+    // failed block splits the skipped range
+    let merged = merge_coverage(
+        vec![range(0, 50, CoverageStatus::SkippedUnchanged)],
+        range(20, 21, CoverageStatus::Failed),
+    );
+    assert_eq!(
+        bounds(&merged),
+        vec![
+            (0, 20, "skipped_unchanged".to_string()),
+            (20, 21, "failed".to_string()),
+            (21, 50, "skipped_unchanged".to_string()),
+        ]
+    );
+
+    let gaps = find_gaps(&merged, 10, 60);
+    assert_eq!(
+        gaps.iter()
+            .map(|g| (g.block_start, g.block_end, g.status.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (10, 20, Some("skipped_unchanged".to_string())),
+            (20, 21, Some("failed".to_string())),
+            (21, 50, Some("skipped_unchanged".to_string())),
+            (50, 60, None),
+        ]
+    );
+}
@@ -0,0 +1,279 @@
+use crate::db::model::transaction::{
+    BlockCounterpartyDbObj, BlockDbObj, ScanDbObj, TxDbObj, TxTraceDbObj, WEI_IN_ETH,
+};
+use crate::db::ops::transaction::{delete_scan, insert_scan};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::block::{save_inspected_block, InspectedBlock};
+use crate::scan::coverage::{record_coverage, CoverageStatus};
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sqlx::SqlitePool;
+use web3::types::{Address, H256};
+
+/// Seconds between blocks of the synthetic chain
+const BLOCK_TIME: i64 = 12;
+/// Timestamp of block_start of the synthetic chain
+const GENESIS_TIMESTAMP: i64 = 1717200000;
+/// Chance of an event in a block, one in given number of blocks
+const WITHDRAWAL_EVERY: u64 = 400;
+const MEV_PAYMENT_EVERY: u64 = 6000;
+const INCOMING_EVERY: u64 = 3000;
+const OUTGOING_EVERY: u64 = 5000;
+
+#[derive(Debug, Clone, Parser)]
+pub struct DemoCommand {
+    /// Number of addresses to generate data for
+    #[arg(long, default_value_t = 3)]
+    addresses: usize,
+    #[arg(long, default_value_t = 20_000_000)]
+    block_start: u64,
+    /// Length of the synthetic chain
+    #[arg(long, default_value_t = 100_000)]
+    blocks: u64,
+    /// Same seed generates the same addresses and blocks
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+fn random_address(rng: &mut StdRng) -> String {
+    format!("{:#x}", Address::from(rng.gen::<[u8; 20]>()))
+}
+
+fn random_wei(rng: &mut StdRng, min_eth: f64, max_eth: f64) -> u128 {
+    (rng.gen_range(min_eth..max_eth) * WEI_IN_ETH as f64) as u128
+}
+
+/// Transfer made in a demo block
+struct DemoTransfer {
+    from: String,
+    to: String,
+    value: u128,
+}
+
+/// Generate blocks of one address. Every block adds up the same way inspect_block
+/// would find it: balance diff equals rewards plus incoming minus outgoing.
+fn generate_blocks(
+    rng: &mut StdRng,
+    address: &str,
+    builders: &[String],
+    block_start: u64,
+    block_end: u64,
+) -> Vec<InspectedBlock> {
+    let mut inspected = Vec::new();
+    let mut balance = 0_u128;
+    for block_num in block_start..block_end {
+        let block_miner = builders[rng.gen_range(0..builders.len())].clone();
+        let mut consensus_reward = 0_u128;
+        let mut mev_reward = 0_u128;
+        let mut transfers = Vec::new();
+
+        if block_num == block_start {
+            // opening deposit, so the validator has something to withdraw
+            transfers.push(DemoTransfer {
+                from: random_address(rng),
+                to: address.to_string(),
+                value: 32 * WEI_IN_ETH as u128,
+            });
+        }
+        if rng.gen_range(0..WITHDRAWAL_EVERY) == 0 {
+            // withdrawals are given in gwei
+            consensus_reward = random_wei(rng, 0.015, 0.02) / 1_000_000_000 * 1_000_000_000;
+        }
+        if rng.gen_range(0..MEV_PAYMENT_EVERY) == 0 {
+            mev_reward = random_wei(rng, 0.01, 0.3);
+            transfers.push(DemoTransfer {
+                from: block_miner.clone(),
+                to: address.to_string(),
+                value: mev_reward,
+            });
+        }
+        if rng.gen_range(0..INCOMING_EVERY) == 0 {
+            transfers.push(DemoTransfer {
+                from: random_address(rng),
+                to: address.to_string(),
+                value: random_wei(rng, 0.1, 5.0),
+            });
+        }
+        if balance > WEI_IN_ETH as u128 && rng.gen_range(0..OUTGOING_EVERY) == 0 {
+            transfers.push(DemoTransfer {
+                from: address.to_string(),
+                to: random_address(rng),
+                value: rng.gen_range(0..balance / 5),
+            });
+        }
+        if consensus_reward == 0 && transfers.is_empty() {
+            continue;
+        }
+
+        let mut amount_incoming = 0_u128;
+        let mut amount_outgoing = 0_u128;
+        let mut txs = Vec::new();
+        let mut traces = Vec::new();
+        let mut counterparties: Vec<BlockCounterpartyDbObj> = Vec::new();
+        for transfer in transfers {
+            let (counterparty, amount_in, amount_out) = if transfer.from == address {
+                amount_outgoing += transfer.value;
+                (&transfer.to, 0, transfer.value)
+            } else if transfer.from != block_miner {
+                amount_incoming += transfer.value;
+                (&transfer.from, transfer.value, 0)
+            } else {
+                (&transfer.from, 0, 0)
+            };
+            if amount_in + amount_out > 0 {
+                counterparties.push(BlockCounterpartyDbObj {
+                    address: address.to_string(),
+                    block_number: block_num as i64,
+                    counterparty: counterparty.clone(),
+                    amount_in: amount_in.to_string(),
+                    amount_out: amount_out.to_string(),
+                });
+            }
+            let tx = TxDbObj {
+                address: address.to_string(),
+                tx_hash: format!("{:#x}", H256::from(rng.gen::<[u8; 32]>())),
+                block_number: block_num as i64,
+                block_index: rng.gen_range(0..200),
+                gas_used: "21000".to_string(),
+            };
+            traces.push(TxTraceDbObj {
+                address: address.to_string(),
+                tx_hash: tx.tx_hash.clone(),
+                block_number: tx.block_number,
+                block_index: tx.block_index,
+                trace_index: 0,
+                from_addr: transfer.from,
+                to_addr: transfer.to,
+                value: transfer.value.to_string(),
+                gas_used: tx.gas_used.clone(),
+            });
+            txs.push(tx);
+        }
+        let balance_diff =
+            (consensus_reward + mev_reward + amount_incoming) as i128 - amount_outgoing as i128;
+        balance = (balance as i128 + balance_diff) as u128;
+
+        inspected.push(InspectedBlock {
+            block: BlockDbObj {
+                address: address.to_string(),
+                block_number: block_num as i64,
+                timestamp: block_timestamp(block_start, block_num),
+                balance: balance.to_string(),
+                balance_diff: balance_diff.to_string(),
+                updated: chrono::Utc::now(),
+                block_miner,
+                consensus_reward: consensus_reward.to_string(),
+                mev_reward: mev_reward.to_string(),
+                block_reward: "0".to_string(),
+                amount_incoming: amount_incoming.to_string(),
+                amount_outgoing: amount_outgoing.to_string(),
+                trace_mode: "trace_block".to_string(),
+                unexplained_value: None,
+                reconciled: true,
+            },
+            txs,
+            traces,
+            counterparties,
+        });
+    }
+    inspected
+}
+
+fn block_timestamp(block_start: u64, block_num: u64) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::from_timestamp(
+        GENESIS_TIMESTAMP + (block_num - block_start) as i64 * BLOCK_TIME,
+        0,
+    )
+    .unwrap()
+}
+
+/// Fill scan tables with a synthetic chain, so the dashboard can be used without a node
+pub async fn demo_command(
+    conn: SqlitePool,
+    demo_command: DemoCommand,
+) -> Result<(), WebPortalError> {
+    let DemoCommand {
+        addresses,
+        block_start,
+        blocks,
+        seed,
+    } = demo_command;
+    let block_end = block_start + blocks;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let builders: Vec<String> = (0..5).map(|_| random_address(&mut rng)).collect();
+
+    for _ in 0..addresses {
+        let address = random_address(&mut rng);
+        let inspected = generate_blocks(&mut rng, &address, &builders, block_start, block_end);
+        log::info!(
+            "Generating {} demo blocks for address {}",
+            inspected.len(),
+            address
+        );
+
+        let mut db_tx = conn
+            .begin()
+            .await
+            .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+        delete_scan(&mut *db_tx, &address)
+            .await
+            .map_err(|e| err_custom_create!("Error deleting previous scan: {}", e))?;
+        insert_scan(
+            &mut *db_tx,
+            &ScanDbObj {
+                address: address.clone(),
+                first_block_number: block_start as i64,
+                first_block_timestamp: block_timestamp(block_start, block_start),
+                next_block_number: block_end as i64,
+                next_block_timestamp: block_timestamp(block_start, block_end),
+                finality_policy: None,
+                finality_block_number: None,
+                follow: false,
+            },
+        )
+        .await
+        .map_err(|e| err_custom_create!("Error inserting scan: {}", e))?;
+        for block in &inspected {
+            save_inspected_block(&mut db_tx, block).await?;
+        }
+        record_coverage(
+            &mut db_tx,
+            &address,
+            block_start,
+            block_end,
+            CoverageStatus::Inspected,
+        )
+        .await?;
+        db_tx
+            .commit()
+            .await
+            .map_err(|e| err_custom_create!("Error committing demo data: {}", e))?;
+    }
+    log::info!("Demo data generated for {} addresses", addresses);
+    Ok(())
+}
+
+#[test]
+fn generate_blocks_test() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let address = random_address(&mut rng);
+    let builders = vec![random_address(&mut rng)];
+    let inspected = generate_blocks(&mut rng, &address, &builders, 1000, 51000);
+
+    assert!(inspected.len() > 100);
+    let mut balance = 0_i128;
+    for block in &inspected {
+        let b = &block.block;
+        let parse = |v: &str| v.parse::<i128>().unwrap();
+        assert_eq!(
+            parse(&b.balance_diff),
+            parse(&b.consensus_reward) + parse(&b.mev_reward) + parse(&b.amount_incoming)
+                - parse(&b.amount_outgoing)
+        );
+        balance += parse(&b.balance_diff);
+        assert_eq!(balance, parse(&b.balance));
+    }
+}
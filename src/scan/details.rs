@@ -0,0 +1,117 @@
+use crate::db::model::transaction::{BlockDbObj, TxDbObj, TxTraceDbObj};
+use crate::db::ops::transaction::{
+    get_block, get_block_tx_traces, get_block_txs, get_tx_traces_by_hash, get_txs_by_hash,
+};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// Transaction with its calls that moved value from or to the scanned address
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TxWithTraces {
+    #[serde(flatten)]
+    pub tx: TxDbObj,
+    pub traces: Vec<TxTraceDbObj>,
+}
+
+/// Block with transactions explaining its balance change
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockWithTxs {
+    #[serde(flatten)]
+    pub block: BlockDbObj,
+    pub txs: Vec<TxWithTraces>,
+}
+
+/// Put every trace under the transaction it belongs to
+fn nest_traces(txs: Vec<TxDbObj>, traces: Vec<TxTraceDbObj>) -> Vec<TxWithTraces> {
+    let mut nested: Vec<TxWithTraces> = txs
+        .into_iter()
+        .map(|tx| TxWithTraces {
+            tx,
+            traces: Vec::new(),
+        })
+        .collect();
+    for trace in traces {
+        let tx = nested.iter_mut().find(|nested| {
+            nested.tx.address == trace.address
+                && nested.tx.tx_hash == trace.tx_hash
+                && nested.tx.block_number == trace.block_number
+                && nested.tx.block_index == trace.block_index
+        });
+        match tx {
+            Some(tx) => tx.traces.push(trace),
+            None => log::warn!("Trace without transaction {}", trace.tx_hash),
+        }
+    }
+    nested
+}
+
+pub async fn get_block_with_txs(
+    conn: &SqlitePool,
+    address: &str,
+    block_number: i64,
+) -> Result<Option<BlockWithTxs>, WebPortalError> {
+    let Some(block) = get_block(conn, address, block_number)
+        .await
+        .map_err(|e| err_custom_create!("Error getting block: {}", e))?
+    else {
+        return Ok(None);
+    };
+    let txs = get_block_txs(conn, address, block_number)
+        .await
+        .map_err(|e| err_custom_create!("Error getting block transactions: {}", e))?;
+    let traces = get_block_tx_traces(conn, address, block_number)
+        .await
+        .map_err(|e| err_custom_create!("Error getting block traces: {}", e))?;
+    Ok(Some(BlockWithTxs {
+        block,
+        txs: nest_traces(txs, traces),
+    }))
+}
+
+/// Transaction as saved for every scanned address, empty when it is not known
+pub async fn get_tx_with_traces(
+    conn: &SqlitePool,
+    tx_hash: &str,
+) -> Result<Vec<TxWithTraces>, WebPortalError> {
+    let txs = get_txs_by_hash(conn, tx_hash)
+        .await
+        .map_err(|e| err_custom_create!("Error getting transaction: {}", e))?;
+    let traces = get_tx_traces_by_hash(conn, tx_hash)
+        .await
+        .map_err(|e| err_custom_create!("Error getting transaction traces: {}", e))?;
+    Ok(nest_traces(txs, traces))
+}
+
+#[test]
+fn nest_traces_test() {
+    let tx = |tx_hash: &str, block_index| TxDbObj {
+        address: "0x01".to_string(),
+        tx_hash: tx_hash.to_string(),
+        block_number: 10,
+        block_index,
+        gas_used: "21000".to_string(),
+    };
+    let trace = |tx: &TxDbObj, trace_index| TxTraceDbObj {
+        address: tx.address.clone(),
+        tx_hash: tx.tx_hash.clone(),
+        block_number: tx.block_number,
+        block_index: tx.block_index,
+        trace_index,
+        from_addr: "0x02".to_string(),
+        to_addr: "0x01".to_string(),
+        value: "1".to_string(),
+        gas_used: "0".to_string(),
+    };
+    let (tx_a, tx_b) = (tx("0xaa", 1), tx("0xbb", 2));
+    let traces = vec![trace(&tx_a, 0), trace(&tx_b, 0), trace(&tx_a, 1)];
+
+    let nested = nest_traces(vec![tx_a.clone(), tx_b], traces);
+    assert_eq!(nested.len(), 2);
+    assert_eq!(nested[0].tx, tx_a);
+    assert_eq!(nested[0].traces.len(), 2);
+    assert_eq!(nested[1].traces.len(), 1);
+}
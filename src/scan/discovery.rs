@@ -0,0 +1,142 @@
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::balance::{cached_get_balance, prefetch_balances};
+use crate::scan::block::sum_withdrawals;
+use crate::scan::rpc::{batch_call, rpc_call};
+use crate::scan::run::FAST_FORWARD_WINDOW;
+use crate::scan::transport::ScanWeb3;
+use serde_json::json;
+use std::collections::BTreeSet;
+use web3::types::{Address, Block, BlockNumber, TraceFilterBuilder, H256};
+
+/// Number of blocks asked for in one trace_filter call
+pub const TRACE_FILTER_CHUNK: u64 = 1000;
+
+/// How the scanner finds blocks worth inspecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DiscoveryStrategy {
+    /// Probe balance in windows and walk the blocks where it changed
+    #[default]
+    BalanceProbe,
+    /// Ask the node for traces touching the address (needs trace_filter)
+    TraceFilter,
+}
+
+/// Blocks in [block_start, block_end) with a trace from or to the address
+async fn trace_filter_hits(
+    web3: &ScanWeb3,
+    address: Address,
+    block_start: u64,
+    block_end: u64,
+) -> Result<BTreeSet<u64>, WebPortalError> {
+    let range = TraceFilterBuilder::default()
+        .from_block(BlockNumber::Number(block_start.into()))
+        .to_block(BlockNumber::Number((block_end - 1).into()));
+    // separate calls, because nodes differ in how they combine both address filters
+    let filters = [
+        range.clone().from_address(vec![address]).build(),
+        range.to_address(vec![address]).build(),
+    ];
+
+    let mut hits = BTreeSet::new();
+    for filter in filters {
+        let traces = rpc_call("trace_filter", || web3.trace().filter(filter.clone()))
+            .await
+            .map_err(|e| e.with_msg("Error calling trace_filter"))?;
+        hits.extend(traces.iter().map(|trace| trace.block_number));
+    }
+    Ok(hits)
+}
+
+/// Blocks of the list paying the address without a trace: withdrawals or fee recipient.
+/// Headers are fetched in JSON-RPC batches.
+async fn credited_without_trace(
+    web3: &ScanWeb3,
+    address: Address,
+    blocks: &[u64],
+) -> Result<Vec<u64>, WebPortalError> {
+    let params = blocks
+        .iter()
+        .map(|block_num| {
+            vec![
+                json!(BlockNumber::Number((*block_num).into())),
+                json!(false),
+            ]
+        })
+        .collect();
+    let headers: Vec<Option<Block<H256>>> = batch_call(web3, "eth_getBlockByNumber", params)
+        .await
+        .map_err(|e| e.with_msg("Error getting blocks"))?;
+
+    let mut credited = Vec::new();
+    for (block_num, header) in blocks.iter().zip(headers) {
+        let block = header.ok_or(err_custom_create!("Block info not found {}", block_num))?;
+        if block.author == address || sum_withdrawals(block.withdrawals.as_ref(), address)? != 0 {
+            credited.push(*block_num);
+        }
+    }
+    Ok(credited)
+}
+
+async fn balance_diff(
+    web3: &ScanWeb3,
+    address: Address,
+    block_from: u64,
+    block_to: u64,
+) -> Result<i128, WebPortalError> {
+    let balance_from = cached_get_balance(web3.clone(), address, block_from).await?;
+    let balance_to = cached_get_balance(web3.clone(), address, block_to).await?;
+    Ok(balance_to.as_u128() as i128 - balance_from.as_u128() as i128)
+}
+
+/// Find blocks in [block_start, block_end) that change the balance of the address.
+/// Traces come from trace_filter. Withdrawals and fee recipient payments have no traces,
+/// so windows where balance changed more than the trace hits explain are searched block by block.
+pub async fn discover_blocks(
+    web3: &ScanWeb3,
+    address: Address,
+    block_start: u64,
+    block_end: u64,
+) -> Result<BTreeSet<u64>, WebPortalError> {
+    let mut blocks = trace_filter_hits(web3, address, block_start, block_end).await?;
+
+    let mut window_bounds = vec![block_start.saturating_sub(1)];
+    window_bounds.extend(
+        (block_start..block_end)
+            .step_by(FAST_FORWARD_WINDOW as usize)
+            .map(|window_start| std::cmp::min(window_start + FAST_FORWARD_WINDOW, block_end) - 1),
+    );
+    prefetch_balances(web3, address, &window_bounds).await?;
+
+    let mut window_start = block_start;
+    while window_start < block_end {
+        let window_end = std::cmp::min(window_start + FAST_FORWARD_WINDOW, block_end);
+
+        let window_diff = balance_diff(
+            web3,
+            address,
+            window_start.saturating_sub(1),
+            window_end - 1,
+        )
+        .await?;
+        if window_diff != 0 {
+            let mut explained_diff = 0;
+            for hit in blocks.range(window_start..window_end) {
+                explained_diff += balance_diff(web3, address, hit.saturating_sub(1), *hit).await?;
+            }
+            if explained_diff != window_diff {
+                log::debug!(
+                    "Balance change in {}..{} not explained by traces, checking blocks",
+                    window_start,
+                    window_end
+                );
+                let candidates: Vec<u64> = (window_start..window_end)
+                    .filter(|block_num| !blocks.contains(block_num))
+                    .collect();
+                blocks.extend(credited_without_trace(web3, address, &candidates).await?);
+            }
+        }
+        window_start = window_end;
+    }
+    Ok(blocks)
+}
@@ -0,0 +1,150 @@
+use crate::db::model::transaction::FailedBlockDbObj;
+use crate::db::ops::transaction::{
+    delete_failed_block, get_due_failed_blocks, get_failed_block, upsert_failed_block,
+};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::metrics;
+use crate::scan::block::{inspect_block, save_inspected_block, InspectedBlock};
+use crate::scan::capabilities::TraceMode;
+use crate::scan::coverage::{record_coverage, CoverageStatus};
+use crate::scan::transport::ScanWeb3;
+use crate::webhook::{notify_new_deliveries, queue_block_events};
+use sqlx::{SqliteConnection, SqlitePool};
+use web3::types::Address;
+
+/// Delay before the first retry, doubled with every failed attempt
+const RETRY_BASE_DELAY_SECS: i64 = 60;
+/// Retries are never delayed more than that
+const RETRY_MAX_DELAY_SECS: i64 = 24 * 3600;
+
+pub type InspectResult = Result<Option<InspectedBlock>, WebPortalError>;
+
+fn retry_delay(attempts: i64) -> chrono::Duration {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    chrono::Duration::seconds(std::cmp::min(
+        RETRY_BASE_DELAY_SECS * 2_i64.pow(exponent),
+        RETRY_MAX_DELAY_SECS,
+    ))
+}
+
+async fn record_failure(
+    conn: &mut SqliteConnection,
+    address: &str,
+    block_num: u64,
+    error: String,
+) -> Result<(), WebPortalError> {
+    let now = chrono::Utc::now();
+    let prev = get_failed_block(&mut *conn, address, block_num as i64)
+        .await
+        .map_err(|e| err_custom_create!("Error getting failed block: {}", e))?;
+    let attempts = prev.as_ref().map(|f| f.attempts).unwrap_or(0) + 1;
+    upsert_failed_block(
+        &mut *conn,
+        &FailedBlockDbObj {
+            address: address.to_string(),
+            block_number: block_num as i64,
+            error,
+            attempts,
+            first_failed: prev.map(|f| f.first_failed).unwrap_or(now),
+            last_attempt: now,
+            next_retry: now + retry_delay(attempts),
+        },
+    )
+    .await
+    .map_err(|e| err_custom_create!("Error saving failed block: {}", e))?;
+    Ok(())
+}
+
+/// Save inspection result of the block. Failed blocks go to the retry queue,
+/// inspected ones are removed from it, including unreconciled blocks, as inspecting
+/// them again gives the same result. Webhook deliveries are queued too,
+/// call notify_new_deliveries after commit.
+pub async fn save_inspect_result(
+    conn: &mut SqliteConnection,
+    address: Address,
+    block_num: u64,
+    result: &InspectResult,
+) -> Result<(), WebPortalError> {
+    let address = format!("{:#x}", address);
+    let error = match result {
+        Ok(Some(inspected)) => {
+            save_inspected_block(&mut *conn, inspected).await?;
+            queue_block_events(&mut *conn, inspected).await?;
+            None
+        }
+        Ok(None) => None,
+        Err(e) => Some(e.to_string()),
+    };
+    let status = if let Some(error) = error {
+        log::warn!("Error inspecting block {}: {}", block_num, error);
+        record_failure(&mut *conn, &address, block_num, error).await?;
+        CoverageStatus::Failed
+    } else {
+        delete_failed_block(&mut *conn, &address, block_num as i64)
+            .await
+            .map_err(|e| err_custom_create!("Error deleting failed block: {}", e))?;
+        CoverageStatus::Inspected
+    };
+    metrics::inc_blocks_inspected(&status.to_string());
+    record_coverage(conn, &address, block_num, block_num + 1, status).await
+}
+
+/// Inspect the block and save the result without moving the scan pointer
+pub async fn inspect_and_save_block(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    block_num: u64,
+    trace_mode: TraceMode,
+) -> Result<(), WebPortalError> {
+    let result = inspect_block(web3.clone(), address, block_num, trace_mode).await;
+
+    let mut db_tx = db
+        .begin()
+        .await
+        .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+    save_inspect_result(&mut db_tx, address, block_num, &result).await?;
+    db_tx
+        .commit()
+        .await
+        .map_err(|e| err_custom_create!("Error committing block {}: {}", block_num, e))?;
+    notify_new_deliveries();
+    Ok(())
+}
+
+/// Inspect again failed blocks whose retry time has come
+pub async fn retry_failed_blocks(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    trace_mode: TraceMode,
+) -> Result<(), WebPortalError> {
+    let due = get_due_failed_blocks(db, &format!("{:#x}", address), chrono::Utc::now())
+        .await
+        .map_err(|e| err_custom_create!("Error getting failed blocks: {}", e))?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    log::info!("Retrying {} failed blocks", due.len());
+
+    for failed_block in due {
+        let block_num = failed_block.block_number as u64;
+        log::info!(
+            "Retrying block {} (attempt {})",
+            block_num,
+            failed_block.attempts + 1
+        );
+        inspect_and_save_block(web3, db, address, block_num, trace_mode).await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn retry_delay_test() {
+    assert_eq!(retry_delay(1), chrono::Duration::seconds(60));
+    assert_eq!(retry_delay(2), chrono::Duration::seconds(120));
+    assert_eq!(retry_delay(5), chrono::Duration::seconds(960));
+    assert_eq!(retry_delay(12), chrono::Duration::hours(24));
+    assert_eq!(retry_delay(1000), chrono::Duration::hours(24));
+}
@@ -0,0 +1,109 @@
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::rpc::rpc_call;
+use crate::scan::transport::ScanWeb3;
+use std::fmt::Display;
+use std::str::FromStr;
+use web3::types::{BlockId, BlockNumber};
+
+/// Decides which blocks are final enough to be scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalityPolicy {
+    /// Fixed number of blocks behind the chain head
+    Depth(u64),
+    /// Node's `finalized` block tag
+    Finalized,
+    /// Node's `safe` block tag
+    Safe,
+}
+
+impl Default for FinalityPolicy {
+    fn default() -> Self {
+        FinalityPolicy::Depth(100)
+    }
+}
+
+impl Display for FinalityPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FinalityPolicy::Depth(depth) => write!(f, "depth:{depth}"),
+            FinalityPolicy::Finalized => write!(f, "finalized"),
+            FinalityPolicy::Safe => write!(f, "safe"),
+        }
+    }
+}
+
+impl FromStr for FinalityPolicy {
+    type Err = String;
+
+    /// Accepts `finalized`, `safe`, `depth:<blocks>` or just `<blocks>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "finalized" => Ok(FinalityPolicy::Finalized),
+            "safe" => Ok(FinalityPolicy::Safe),
+            other => {
+                let depth = other.strip_prefix("depth:").unwrap_or(other);
+                depth.parse::<u64>().map(FinalityPolicy::Depth).map_err(|_| {
+                    format!("Invalid finality policy: {s}, expected finalized, safe or depth:<blocks>")
+                })
+            }
+        }
+    }
+}
+
+impl FinalityPolicy {
+    /// Last block number that is considered final.
+    /// Pass head if it is already known (for example from newHeads subscription).
+    pub async fn last_final_block(
+        &self,
+        web3: &ScanWeb3,
+        head: Option<u64>,
+    ) -> Result<u64, WebPortalError> {
+        let tag = match self {
+            FinalityPolicy::Depth(depth) => {
+                let head = if let Some(head) = head {
+                    head
+                } else {
+                    rpc_call("eth_blockNumber", || web3.eth().block_number())
+                        .await
+                        .map_err(|e| e.with_msg("Error getting current block number"))?
+                        .as_u64()
+                };
+                return Ok(head.saturating_sub(*depth));
+            }
+            FinalityPolicy::Finalized => BlockNumber::Finalized,
+            FinalityPolicy::Safe => BlockNumber::Safe,
+        };
+        let block = rpc_call("eth_getBlockByNumber", || {
+            web3.eth().block(BlockId::Number(tag))
+        })
+        .await
+        .map_err(|e| e.with_msg(format!("Error getting {} block", self)))?
+        .ok_or(err_custom_create!("Node returned no {} block", self))?;
+        Ok(block
+            .number
+            .ok_or(err_custom_create!("{} block has no number", self))?
+            .as_u64())
+    }
+}
+
+#[test]
+fn finality_policy_parse_test() {
+    assert_eq!(
+        FinalityPolicy::from_str("finalized"),
+        Ok(FinalityPolicy::Finalized)
+    );
+    assert_eq!(FinalityPolicy::from_str("Safe"), Ok(FinalityPolicy::Safe));
+    assert_eq!(
+        FinalityPolicy::from_str("depth:64"),
+        Ok(FinalityPolicy::Depth(64))
+    );
+    assert_eq!(
+        FinalityPolicy::from_str("12"),
+        Ok(FinalityPolicy::Depth(12))
+    );
+    assert!(FinalityPolicy::from_str("latest").is_err());
+
+    let policy = FinalityPolicy::default();
+    assert_eq!(FinalityPolicy::from_str(&policy.to_string()), Ok(policy));
+}
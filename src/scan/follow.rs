@@ -0,0 +1,141 @@
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::capabilities::resolve_scan_options;
+use crate::scan::rpc::rpc_call;
+use crate::scan::run::{create_web3, scan_address_range, ScanOptions};
+use crate::scan::transport::ScanWeb3;
+use futures_util::StreamExt;
+use sqlx::SqlitePool;
+use std::env;
+use std::time::{Duration, Instant};
+use web3::types::Address;
+
+/// How often the chain head is polled when WebSocket is not available
+const POLL_INTERVAL: Duration = Duration::from_secs(12);
+/// How long to stay in polling mode before trying to reconnect WebSocket
+const WS_RETRY_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Scan all final blocks up to the given chain head
+async fn scan_up_to_head(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    block_start: u64,
+    head: u64,
+    options: ScanOptions,
+) -> Result<(), WebPortalError> {
+    let last_final_block = options.finality.last_final_block(web3, Some(head)).await?;
+    scan_address_range(
+        web3.clone(),
+        db.clone(),
+        address,
+        block_start,
+        last_final_block,
+        options,
+    )
+    .await
+}
+
+/// Follow the chain head using newHeads subscription.
+/// Returns when subscription ends, so caller can fall back to polling.
+async fn follow_new_heads(
+    ws_endpoint: &str,
+    db: &SqlitePool,
+    address: Address,
+    block_start: u64,
+    options: ScanOptions,
+) -> Result<(), WebPortalError> {
+    let web3 = create_web3()?;
+    let ws = web3::transports::WebSocket::new(ws_endpoint)
+        .await
+        .map_err(|e| err_custom_create!("Error connecting to {}: {}", ws_endpoint, e))?;
+    let web3_ws = web3::Web3::new(ws);
+
+    let mut new_heads = web3_ws
+        .eth_subscribe()
+        .subscribe_new_heads()
+        .await
+        .map_err(|e| err_custom_create!("Error subscribing to newHeads: {}", e))?;
+    log::info!("Subscribed to newHeads on {}", ws_endpoint);
+
+    while let Some(header) = new_heads.next().await {
+        let header = header.map_err(|e| err_custom_create!("Error in newHeads stream: {}", e))?;
+        let Some(head) = header.number else {
+            log::debug!("Skipping pending header without number");
+            continue;
+        };
+        log::debug!("New head: {}", head);
+        scan_up_to_head(&web3, db, address, block_start, head.as_u64(), options).await?;
+    }
+    Ok(())
+}
+
+/// Follow the chain head by polling block number over HTTP.
+/// When retry_after is set, returns after that time so WebSocket can be tried again.
+async fn follow_polling(
+    db: &SqlitePool,
+    address: Address,
+    block_start: u64,
+    options: ScanOptions,
+    retry_after: Option<Duration>,
+) -> Result<(), WebPortalError> {
+    let web3 = create_web3()?;
+    let started = Instant::now();
+    loop {
+        match rpc_call("eth_blockNumber", || web3.eth().block_number()).await {
+            Ok(head) => {
+                if let Err(e) =
+                    scan_up_to_head(&web3, db, address, block_start, head.as_u64(), options).await
+                {
+                    log::warn!("Error scanning up to head {}: {}", head, e);
+                }
+            }
+            Err(e) => {
+                log::warn!("Error getting current block number: {}", e);
+            }
+        }
+        if let Some(retry_after) = retry_after {
+            if started.elapsed() > retry_after {
+                return Ok(());
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Scan address continuously, inspecting blocks as soon as they become final.
+/// Uses newHeads subscription when SCANNER_RPC_WS_NODE is set, otherwise polls over HTTP.
+pub async fn follow_address(
+    db: SqlitePool,
+    address: Address,
+    block_start: u64,
+    options: ScanOptions,
+) -> Result<(), WebPortalError> {
+    // detect node capabilities once, not on every new head
+    let options = ScanOptions {
+        follow: true,
+        ..resolve_scan_options(&create_web3()?, options).await?
+    };
+
+    let ws_endpoint = env::var("SCANNER_RPC_WS_NODE").ok();
+    if ws_endpoint.is_none() {
+        log::info!("SCANNER_RPC_WS_NODE not set, following chain head by polling");
+    }
+
+    loop {
+        if let Some(ws_endpoint) = &ws_endpoint {
+            match follow_new_heads(ws_endpoint, &db, address, block_start, options).await {
+                Ok(()) => log::warn!("newHeads subscription ended, falling back to polling"),
+                Err(e) => log::warn!("WebSocket follow failed, falling back to polling: {}", e),
+            }
+        }
+        follow_polling(
+            &db,
+            address,
+            block_start,
+            options,
+            ws_endpoint.as_ref().map(|_| WS_RETRY_INTERVAL),
+        )
+        .await?;
+    }
+}
@@ -0,0 +1,223 @@
+use crate::db::model::label::AddressLabelDbObj;
+use crate::db::ops::label::{get_address_labels, upsert_address_label};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use web3::types::Address;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelCategory {
+    Exchange,
+    Builder,
+    OwnWallet,
+    Pool,
+    Other,
+}
+
+impl Display for LabelCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelCategory::Exchange => write!(f, "exchange"),
+            LabelCategory::Builder => write!(f, "builder"),
+            LabelCategory::OwnWallet => write!(f, "own_wallet"),
+            LabelCategory::Pool => write!(f, "pool"),
+            LabelCategory::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Label as sent by the client or read from CSV, address is taken from the path or address column
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelInput {
+    pub name: String,
+    pub category: LabelCategory,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// One row of the label CSV file
+#[derive(Serialize, Deserialize, Debug)]
+struct LabelCsvRow {
+    address: String,
+    name: String,
+    category: LabelCategory,
+    #[serde(default)]
+    notes: Option<String>,
+}
+
+/// Addresses are stored lowercase, like all other addresses in the database
+pub fn normalize_address(address: &str) -> Result<String, WebPortalError> {
+    Address::from_str(address.trim())
+        .map(|address| format!("{address:#x}"))
+        .map_err(|_| err_custom_create!("Invalid address {}", address))
+}
+
+pub fn label_from_input(
+    address: &str,
+    input: LabelInput,
+) -> Result<AddressLabelDbObj, WebPortalError> {
+    if input.name.trim().is_empty() {
+        return Err(err_custom_create!("Label name is empty"));
+    }
+    Ok(AddressLabelDbObj {
+        address: normalize_address(address)?,
+        name: input.name.trim().to_string(),
+        category: input.category.to_string(),
+        notes: input.notes.filter(|notes| !notes.is_empty()),
+        updated: chrono::Utc::now(),
+    })
+}
+
+fn parse_labels_csv(content: &str) -> Result<Vec<AddressLabelDbObj>, WebPortalError> {
+    let mut labels = Vec::new();
+    for row in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes())
+        .deserialize()
+    {
+        let row: LabelCsvRow = row.map_err(|e| err_custom_create!("Invalid label CSV: {}", e))?;
+        labels.push(label_from_input(
+            &row.address,
+            LabelInput {
+                name: row.name,
+                category: row.category,
+                notes: row.notes,
+            },
+        )?);
+    }
+    Ok(labels)
+}
+
+/// Add or replace labels from CSV with address, name, category and notes columns.
+/// Nothing is saved when any row is invalid.
+pub async fn import_labels_csv(conn: &SqlitePool, content: &str) -> Result<usize, WebPortalError> {
+    let labels = parse_labels_csv(content)?;
+    let mut db_tx = conn
+        .begin()
+        .await
+        .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+    for label in &labels {
+        upsert_address_label(&mut *db_tx, label)
+            .await
+            .map_err(|e| err_custom_create!("Error saving label: {}", e))?;
+    }
+    db_tx
+        .commit()
+        .await
+        .map_err(|e| err_custom_create!("Error committing labels: {}", e))?;
+    Ok(labels.len())
+}
+
+pub async fn export_labels_csv(conn: &SqlitePool) -> Result<String, WebPortalError> {
+    let labels = get_address_labels(conn)
+        .await
+        .map_err(|e| err_custom_create!("Error getting labels: {}", e))?;
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for label in labels {
+        writer
+            .serialize(LabelCsvRow {
+                address: label.address,
+                name: label.name,
+                category: serde_json::from_value(Value::String(label.category))
+                    .unwrap_or(LabelCategory::Other),
+                notes: label.notes,
+            })
+            .map_err(|e| err_custom_create!("Error writing label CSV: {}", e))?;
+    }
+    let content = writer
+        .into_inner()
+        .map_err(|e| err_custom_create!("Error writing label CSV: {}", e))?;
+    String::from_utf8(content).map_err(|e| err_custom_create!("Invalid label CSV: {}", e))
+}
+
+pub async fn get_label_map(
+    conn: &SqlitePool,
+) -> Result<HashMap<String, AddressLabelDbObj>, WebPortalError> {
+    Ok(get_address_labels(conn)
+        .await
+        .map_err(|e| err_custom_create!("Error getting labels: {}", e))?
+        .into_iter()
+        .map(|label| (label.address.clone(), label))
+        .collect())
+}
+
+/// Every JSON object with a labeled address in one of its fields (or in a list field)
+/// gets `labels` field mapping the address to its label
+pub fn attach_labels(value: &mut Value, labels: &HashMap<String, AddressLabelDbObj>) {
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| attach_labels(item, labels)),
+        Value::Object(object) => {
+            let mut found = serde_json::Map::new();
+            for field in object.values_mut() {
+                let strings: Vec<&str> = match field {
+                    Value::String(s) => vec![s.as_str()],
+                    Value::Array(items) => items.iter().filter_map(Value::as_str).collect(),
+                    _ => Vec::new(),
+                };
+                for s in strings {
+                    if let Some(label) = labels.get(&s.to_lowercase()) {
+                        found.insert(
+                            label.address.clone(),
+                            serde_json::to_value(label).unwrap_or_default(),
+                        );
+                    }
+                }
+                attach_labels(field, labels);
+            }
+            if !found.is_empty() {
+                object.insert("labels".to_string(), Value::Object(found));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn labels_test() {
+    let csv = "address,name,category,notes\n\
+               0x00000000000000000000000000000000000000AA, Exchange hot wallet ,exchange,\n\
+               0x00000000000000000000000000000000000000bb,Our cold wallet,own_wallet,ledger\n";
+    let labels = parse_labels_csv(csv).unwrap();
+    assert_eq!(
+        labels[0].address,
+        "0x00000000000000000000000000000000000000aa"
+    );
+    assert_eq!(labels[0].name, "Exchange hot wallet");
+    assert_eq!(labels[0].notes, None);
+    assert_eq!(labels[1].category, "own_wallet");
+    assert!(parse_labels_csv("address,name,category\n0x01,Bad,exchange\n").is_err());
+    assert!(parse_labels_csv(
+        "address,name,category\n0x00000000000000000000000000000000000000aa,X,bank\n"
+    )
+    .is_err());
+
+    let map: HashMap<_, _> = labels
+        .into_iter()
+        .map(|label| (label.address.clone(), label))
+        .collect();
+    let mut value = serde_json::json!([{
+        "fromAddr": "0x00000000000000000000000000000000000000aa",
+        "toAddr": "0x00000000000000000000000000000000000000cc",
+        "traces": [{"toAddr": "0x00000000000000000000000000000000000000bb"}],
+    }]);
+    attach_labels(&mut value, &map);
+    let labels = value[0]["labels"].as_object().unwrap();
+    assert_eq!(labels.len(), 1);
+    assert_eq!(
+        labels["0x00000000000000000000000000000000000000aa"]["name"],
+        "Exchange hot wallet"
+    );
+    assert_eq!(
+        value[0]["traces"][0]["labels"]["0x00000000000000000000000000000000000000bb"]["category"],
+        "own_wallet"
+    );
+}
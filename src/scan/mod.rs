@@ -1,5 +1,19 @@
 pub mod api;
 mod balance;
-mod block;
+pub mod block;
+pub mod capabilities;
 pub mod cmd;
+pub mod counterparty;
+pub mod coverage;
+pub mod demo;
+pub mod details;
+pub mod discovery;
+mod failed;
+pub mod finality;
+pub mod follow;
+pub mod labels;
+pub mod queue;
+mod rpc;
 pub mod run;
+mod trace;
+pub mod transport;
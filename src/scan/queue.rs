@@ -0,0 +1,170 @@
+use crate::db::ops::transaction::get_scan;
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::coverage::fill_gap;
+use crate::scan::run::{create_web3, scan_address, ScanOptions};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use web3::types::Address;
+
+/// What a queued job does with the blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanJobKind {
+    /// Extend the scan from block_start to block_end or the chain head
+    #[default]
+    Scan,
+    /// Inspect again every block of [block_start, block_end), which must be already scanned
+    FillGap,
+}
+
+/// Scan requested over the API, for a scan row that already exists
+#[derive(Debug, Clone)]
+pub struct ScanJob {
+    pub address: Address,
+    pub block_start: u64,
+    pub block_end: Option<u64>,
+    pub options: ScanOptions,
+    pub kind: ScanJobKind,
+}
+
+/// Handle to the background scanner, jobs are run one at a time in order of arrival.
+/// Jobs are kept in memory only, scans queued before a restart have to be requested again.
+#[derive(Debug, Clone)]
+pub struct ScanQueue {
+    sender: mpsc::UnboundedSender<ScanJob>,
+    running: Arc<Mutex<HashMap<Address, AbortHandle>>>,
+}
+
+impl ScanQueue {
+    /// Spawn the worker on the current runtime
+    pub fn start(conn: SqlitePool) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queue = ScanQueue {
+            sender,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        };
+        actix_web::rt::spawn(run_worker(conn, receiver, queue.running.clone()));
+        queue
+    }
+
+    pub fn push(&self, job: ScanJob) -> Result<(), WebPortalError> {
+        log::info!("Queueing {:?} job of {:#x}", job.kind, job.address);
+        self.sender
+            .send(job)
+            .map_err(|_| err_custom_create!("Scan worker is not running"))
+    }
+
+    /// Stop the running scan of the address, returns false when it was not running.
+    /// Queued jobs of the address are skipped anyway once its scan row is deleted.
+    pub fn cancel(&self, address: Address) -> bool {
+        match self.running.lock().unwrap().remove(&address) {
+            Some(handle) => {
+                log::warn!("Cancelling running scan of {:#x}", address);
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Run the job unless its scan was deleted after it was queued
+async fn run_job(conn: SqlitePool, job: ScanJob) -> Result<(), WebPortalError> {
+    if get_scan(&conn, &format!("{:#x}", job.address))
+        .await
+        .map_err(|e| err_custom_create!("Error getting scan: {}", e))?
+        .is_none()
+    {
+        log::info!("Skipping queued scan of deleted {:#x}", job.address);
+        return Ok(());
+    }
+    match job.kind {
+        ScanJobKind::Scan => {
+            // the row can still be deleted before the scan reads it, it must not be created again
+            let options = ScanOptions {
+                existing_only: true,
+                ..job.options
+            };
+            scan_address(conn, job.address, job.block_start, job.block_end, options).await
+        }
+        ScanJobKind::FillGap => {
+            let block_end = job
+                .block_end
+                .ok_or(err_custom_create!("Filling a gap needs block end"))?;
+            fill_gap(
+                create_web3()?,
+                conn,
+                job.address,
+                job.block_start,
+                block_end,
+                job.options.trace_mode,
+            )
+            .await
+        }
+    }
+}
+
+async fn run_worker(
+    conn: SqlitePool,
+    mut receiver: mpsc::UnboundedReceiver<ScanJob>,
+    running: Arc<Mutex<HashMap<Address, AbortHandle>>>,
+) {
+    while let Some(job) = receiver.recv().await {
+        let address = job.address;
+        // registered before the task checks the scan row, so a delete can always cancel it
+        let task = actix_web::rt::spawn(run_job(conn.clone(), job));
+        running.lock().unwrap().insert(address, task.abort_handle());
+        match task.await {
+            Ok(Ok(())) => log::info!("Queued scan of {:#x} finished", address),
+            Ok(Err(e)) => log::error!("Queued scan of {:#x} failed: {}", address, e),
+            Err(e) if e.is_cancelled() => {}
+            Err(e) => log::error!("Queued scan of {:#x} panicked: {}", address, e),
+        }
+        running.lock().unwrap().remove(&address);
+    }
+}
+
+#[tokio::test]
+async fn run_job_deleted_scan_test() {
+    let conn = crate::create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+    let address = Address::from_low_u64_be(1);
+    let job = ScanJob {
+        address,
+        block_start: 100,
+        block_end: None,
+        options: ScanOptions::default(),
+        kind: ScanJobKind::Scan,
+    };
+    // returns before connecting to any node and does not create the scan
+    run_job(conn.clone(), job).await.unwrap();
+    assert!(get_scan(&conn, &format!("{:#x}", address))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn cancel_test() {
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let queue = ScanQueue {
+        sender,
+        running: Arc::new(Mutex::new(HashMap::new())),
+    };
+    let address = Address::from_low_u64_be(1);
+    let task = tokio::spawn(std::future::pending::<()>());
+    queue
+        .running
+        .lock()
+        .unwrap()
+        .insert(address, task.abort_handle());
+
+    assert!(!queue.cancel(Address::from_low_u64_be(2)));
+    assert!(queue.cancel(address));
+    assert!(task.await.unwrap_err().is_cancelled());
+    assert!(!queue.cancel(address));
+}
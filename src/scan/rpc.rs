@@ -0,0 +1,294 @@
+use crate::error::{ErrorBag, WebPortalError};
+use crate::metrics;
+use crate::scan::transport::ScanWeb3;
+use crate::{err_create, err_custom_create};
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use web3::error::TransportError;
+use web3::{BatchTransport, Transport};
+
+/// Delay before the first retry, doubled with every retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Retries are never delayed more than that
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Consecutive failed calls opening the circuit breaker
+const BREAKER_FAILURE_THRESHOLD: u32 = 10;
+/// How long calls are held back after the circuit breaker opens
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
+/// How often callers waiting for the probe of a half open breaker check its result
+const BREAKER_PROBE_POLL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref RPC_CALL_TIMEOUT: Duration = Duration::from_secs(
+        std::env::var("SCANNER_RPC_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30)
+    );
+    static ref RPC_MAX_RETRIES: u32 = std::env::var("SCANNER_RPC_MAX_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
+    /// Maximum number of calls sent in one JSON-RPC batch
+    pub static ref RPC_BATCH_SIZE: usize = std::env::var("SCANNER_RPC_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(50_usize)
+        .max(1);
+    static ref BREAKER: Mutex<CircuitBreaker> = Mutex::new(CircuitBreaker::default());
+}
+
+/// Stops calling the node for a while after too many consecutive failures,
+/// then lets a single call through to check if it is back
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// Probe call of the half open breaker, another one is let through when it takes too long
+    probe_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// None when the call can go now, otherwise how long to wait before asking again
+    fn admit(&mut self, now: Instant) -> Option<Duration> {
+        let open_until = self.open_until?;
+        if now < open_until {
+            return Some(open_until - now);
+        }
+        match self.probe_started {
+            Some(probe_started) if now < probe_started + *RPC_CALL_TIMEOUT => {
+                Some(BREAKER_PROBE_POLL)
+            }
+            _ => {
+                self.probe_started = Some(now);
+                None
+            }
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.open_until.is_some() {
+            log::info!("RPC node responds again, closing circuit breaker");
+        }
+        self.consecutive_failures = 0;
+        self.open_until = None;
+        self.probe_started = None;
+    }
+
+    fn record_failure(&mut self, now: Instant) {
+        self.consecutive_failures += 1;
+        // after cooldown one failed probe is enough to open it again
+        if self.consecutive_failures >= BREAKER_FAILURE_THRESHOLD || self.open_until.is_some() {
+            log::warn!(
+                "{} consecutive RPC failures, holding calls back for {:?}",
+                self.consecutive_failures,
+                BREAKER_COOLDOWN
+            );
+            self.open_until = Some(now + BREAKER_COOLDOWN);
+            self.probe_started = None;
+        }
+    }
+}
+
+/// Wait until the breaker lets the call through, false when that takes longer than max_wait
+async fn wait_for_breaker(breaker: &Mutex<CircuitBreaker>, max_wait: Duration) -> bool {
+    let deadline = Instant::now() + max_wait;
+    loop {
+        let now = Instant::now();
+        let Some(wait) = breaker.lock().unwrap().admit(now) else {
+            return true;
+        };
+        if now >= deadline {
+            return false;
+        }
+        tokio::time::sleep(wait.min(deadline - now)).await;
+    }
+}
+
+/// Rate limits, server errors and connection problems are worth retrying,
+/// other errors mean the node answered and the call is wrong
+fn is_retryable(err: &web3::Error) -> bool {
+    match err {
+        web3::Error::Transport(TransportError::Code(code)) => *code == 429 || *code >= 500,
+        web3::Error::Transport(TransportError::Message(_)) => true,
+        web3::Error::Unreachable | web3::Error::Io(_) => true,
+        web3::Error::Rpc(e) => {
+            let message = e.message.to_lowercase();
+            e.code.code() == -32005
+                || message.contains("rate limit")
+                || message.contains("too many requests")
+        }
+        _ => false,
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    std::cmp::min(
+        RETRY_BASE_DELAY * 2_u32.pow(attempt.min(16)),
+        RETRY_MAX_DELAY,
+    )
+}
+
+/// Call the node with timeout, retrying transient failures with exponential backoff.
+/// While the circuit breaker is open the call waits for it, at most the call timeout per attempt.
+/// Web3 errors are returned as ErrorBag::Web3Error and timeouts as ErrorBag::TimeLimitReached.
+pub async fn rpc_call<T, F, Fut>(method: &str, call: F) -> Result<T, WebPortalError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, web3::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        let err = if !wait_for_breaker(&BREAKER, *RPC_CALL_TIMEOUT).await {
+            // the node is not called, so this is not another failure of it
+            err_custom_create!("RPC circuit breaker is open, not calling {}", method)
+        } else {
+            metrics::inc_rpc_call(method);
+            match tokio::time::timeout(*RPC_CALL_TIMEOUT, call()).await {
+                Ok(Ok(res)) => {
+                    BREAKER.lock().unwrap().record_success();
+                    return Ok(res);
+                }
+                Ok(Err(e)) if !is_retryable(&e) => {
+                    metrics::inc_rpc_error(method, "rpc");
+                    // node answered, so it is alive
+                    BREAKER.lock().unwrap().record_success();
+                    return Err(err_create!(e));
+                }
+                Ok(Err(e)) => {
+                    metrics::inc_rpc_error(method, "retryable");
+                    BREAKER.lock().unwrap().record_failure(Instant::now());
+                    err_create!(e)
+                }
+                Err(_) => {
+                    metrics::inc_rpc_error(method, "timeout");
+                    BREAKER.lock().unwrap().record_failure(Instant::now());
+                    err_create!(*RPC_CALL_TIMEOUT)
+                }
+            }
+        };
+
+        if attempt >= *RPC_MAX_RETRIES {
+            return Err(err);
+        }
+        let delay = retry_delay(attempt);
+        log::warn!(
+            "RPC call {} failed (attempt {}), retrying in {:?}: {}",
+            method,
+            attempt + 1,
+            delay,
+            err
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Call one method with every set of params, sending at most RPC_BATCH_SIZE calls
+/// in one HTTP request. Results are returned in order of params.
+pub async fn batch_call<T: DeserializeOwned>(
+    web3: &ScanWeb3,
+    method: &str,
+    params: Vec<Vec<serde_json::Value>>,
+) -> Result<Vec<T>, WebPortalError> {
+    let transport = web3.transport();
+    let mut results = Vec::with_capacity(params.len());
+    for chunk in params.chunks(*RPC_BATCH_SIZE) {
+        let requests: Vec<_> = chunk
+            .iter()
+            .map(|params| transport.prepare(method, params.clone()))
+            .collect();
+        let responses = rpc_call(method, || transport.send_batch(requests.clone())).await?;
+        if responses.len() != requests.len() {
+            return Err(err_custom_create!(
+                "Batch of {} {} calls returned {} results",
+                requests.len(),
+                method,
+                responses.len()
+            ));
+        }
+        for response in responses {
+            let value = response.map_err(|e| err_create!(e))?;
+            results.push(
+                serde_json::from_value(value)
+                    .map_err(|e| err_custom_create!("Invalid {} result: {}", method, e))?,
+            );
+        }
+    }
+    Ok(results)
+}
+
+#[test]
+fn circuit_breaker_test() {
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::default();
+    for _ in 0..BREAKER_FAILURE_THRESHOLD - 1 {
+        breaker.record_failure(now);
+    }
+    assert_eq!(breaker.admit(now), None);
+    breaker.record_failure(now);
+    assert_eq!(breaker.admit(now), Some(BREAKER_COOLDOWN));
+
+    // single probe after cooldown decides
+    let after_cooldown = now + BREAKER_COOLDOWN;
+    assert_eq!(breaker.admit(after_cooldown), None);
+    assert_eq!(breaker.admit(after_cooldown), Some(BREAKER_PROBE_POLL));
+    breaker.record_failure(after_cooldown);
+    assert_eq!(breaker.admit(after_cooldown), Some(BREAKER_COOLDOWN));
+    breaker.record_success();
+    assert_eq!(breaker.admit(after_cooldown), None);
+
+    // probe taking longer than the call timeout does not block others
+    breaker.open_until = Some(now);
+    assert_eq!(breaker.admit(now), None);
+    assert_eq!(breaker.admit(now + *RPC_CALL_TIMEOUT), None);
+
+    assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+    assert_eq!(retry_delay(20), RETRY_MAX_DELAY);
+}
+
+#[tokio::test]
+async fn circuit_breaker_half_open_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let breaker = Arc::new(Mutex::new(CircuitBreaker {
+        consecutive_failures: BREAKER_FAILURE_THRESHOLD,
+        open_until: Some(Instant::now() + Duration::from_millis(50)),
+        probe_started: None,
+    }));
+    let passed = Arc::new(AtomicUsize::new(0));
+    let callers: Vec<_> = (0..5)
+        .map(|_| {
+            let breaker = breaker.clone();
+            let passed = passed.clone();
+            tokio::spawn(async move {
+                if wait_for_breaker(&breaker, Duration::from_secs(5)).await {
+                    passed.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        })
+        .collect();
+
+    // callers wait for the cooldown, then only the probe goes through
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(passed.load(Ordering::SeqCst), 0);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(passed.load(Ordering::SeqCst), 1);
+
+    // successful probe lets the others through
+    breaker.lock().unwrap().record_success();
+    for caller in callers {
+        caller.await.unwrap();
+    }
+    assert_eq!(passed.load(Ordering::SeqCst), 5);
+
+    // waiting is bounded
+    for _ in 0..BREAKER_FAILURE_THRESHOLD {
+        breaker.lock().unwrap().record_failure(Instant::now());
+    }
+    assert!(!wait_for_breaker(&breaker, Duration::from_millis(50)).await);
+}
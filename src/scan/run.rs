@@ -2,69 +2,189 @@ use crate::db::model::transaction::ScanDbObj;
 use crate::db::ops::transaction::{get_scan, insert_scan, update_scan};
 use crate::err_custom_create;
 use crate::error::WebPortalError;
-use crate::scan::balance::cached_get_balance;
+use crate::scan::balance::{cached_get_balance, is_balance_cached, prefetch_balances};
 use crate::scan::block::inspect_block;
+use crate::scan::capabilities::{resolve_scan_options, TraceMode};
+use crate::scan::coverage::save_skipped_ranges;
+use crate::scan::discovery::{discover_blocks, DiscoveryStrategy, TRACE_FILTER_CHUNK};
+use crate::scan::failed::{retry_failed_blocks, save_inspect_result, InspectResult};
+use crate::scan::finality::FinalityPolicy;
+use crate::scan::rpc::{rpc_call, RPC_BATCH_SIZE};
+use crate::scan::transport::{ScanTransport, ScanWeb3};
+use crate::webhook::notify_new_deliveries;
 use sqlx::SqlitePool;
 use std::env;
+use std::path::Path;
 use web3::types::{Address, BlockId, BlockNumber};
 
+/// Number of blocks skipped at once when the balance did not change
+pub const FAST_FORWARD_WINDOW: u64 = 50;
+
+/// Number of blocks scanned before first_block_number is moved back when extending a scan
+const BACKFILL_CHUNK: u64 = 1000;
+
+/// Which way the scan is extended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanDirection {
+    /// From next_block_number towards the chain head, moving next_block_number
+    Forward,
+    /// Blocks before first_block_number, which is moved only after the whole chunk is done
+    Backfill,
+}
+
+/// Settings shared by all ways of running the scanner
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanOptions {
+    pub finality: FinalityPolicy,
+    pub discovery: DiscoveryStrategy,
+    /// Detected at scan start when not set
+    pub trace_mode: Option<TraceMode>,
+    /// Fail instead of starting a new scan when the scan row is missing
+    pub existing_only: bool,
+    /// Set by follow_address, saved on the scan
+    pub follow: bool,
+}
+
+/// Create web3 client for the node given in SCANNER_RPC_FULL_NODE.
+/// SCANNER_RPC_RECORD saves all calls to the given fixture file,
+/// SCANNER_RPC_REPLAY answers calls from such file without connecting to any node.
+pub fn create_web3() -> Result<ScanWeb3, WebPortalError> {
+    if let Ok(replay_path) = env::var("SCANNER_RPC_REPLAY") {
+        return Ok(web3::Web3::new(ScanTransport::replay(Path::new(
+            &replay_path,
+        ))?));
+    }
+
+    let rpc_endpoint =
+        env::var("SCANNER_RPC_FULL_NODE").unwrap_or_else(|_| "http://localhost:8545".to_string());
+
+    let http = web3::transports::Http::new(&rpc_endpoint)
+        .map_err(|e| err_custom_create!("Error creating transport {}: {}", rpc_endpoint, e))?;
+    let transport = match env::var("SCANNER_RPC_RECORD") {
+        Ok(record_path) => ScanTransport::recording(http, Path::new(&record_path))?,
+        Err(_) => ScanTransport::Http(http),
+    };
+    Ok(web3::Web3::new(transport))
+}
+
+/// Scan row starting (and not yet advanced) at block_start
+pub async fn new_scan_obj(
+    web3: &ScanWeb3,
+    address: Address,
+    block_start: u64,
+    finality: FinalityPolicy,
+    finality_block_number: Option<u64>,
+) -> Result<ScanDbObj, WebPortalError> {
+    let block_info = rpc_call("eth_getBlockByNumber", || {
+        web3.eth()
+            .block(BlockId::Number(BlockNumber::Number(block_start.into())))
+    })
+    .await
+    .map_err(|e| e.with_msg(format!("Error getting block {}", block_start)))?
+    .ok_or(err_custom_create!("Block info not found {}", block_start))?;
+    let timestamp =
+        chrono::DateTime::from_timestamp(block_info.timestamp.as_u64() as i64, 0).unwrap();
+
+    Ok(ScanDbObj {
+        address: format!("{:#x}", address),
+        first_block_number: block_start as i64,
+        first_block_timestamp: timestamp,
+        next_block_number: block_start as i64,
+        next_block_timestamp: timestamp,
+        finality_policy: Some(finality.to_string()),
+        finality_block_number: finality_block_number.map(|block| block as i64),
+        follow: false,
+    })
+}
+
+/// First block with timestamp at or after the given time, found by bisection
+pub async fn find_block_by_timestamp(
+    web3: &ScanWeb3,
+    timestamp: chrono::DateTime<chrono::Utc>,
+) -> Result<u64, WebPortalError> {
+    let block_timestamp = |block_num: u64| async move {
+        rpc_call("eth_getBlockByNumber", || {
+            web3.eth()
+                .block(BlockId::Number(BlockNumber::Number(block_num.into())))
+        })
+        .await
+        .map_err(|e| e.with_msg(format!("Error getting block {}", block_num)))?
+        .ok_or(err_custom_create!("Block info not found {}", block_num))
+        .map(|block| block.timestamp.as_u64() as i64)
+    };
+    let head = rpc_call("eth_blockNumber", || web3.eth().block_number())
+        .await
+        .map_err(|e| e.with_msg("Error getting current block number"))?
+        .as_u64();
+    if block_timestamp(head).await? < timestamp.timestamp() {
+        return Err(err_custom_create!(
+            "No block at or after {} yet",
+            timestamp.to_rfc3339()
+        ));
+    }
+
+    let (mut low, mut high) = (0, head);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if block_timestamp(mid).await? < timestamp.timestamp() {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
 pub async fn scan_address(
     db: SqlitePool,
     address: Address,
     block_start: u64,
     block_end: Option<u64>,
+    options: ScanOptions,
 ) -> Result<(), WebPortalError> {
-    let rpc_endpoint =
-        env::var("SCANNER_RPC_FULL_NODE").unwrap_or_else(|_| "http://localhost:8545".to_string());
+    let finality = options.finality;
+    let web3 = create_web3()?;
 
-    let web3 = web3::Web3::new(web3::transports::Http::new(&rpc_endpoint).unwrap());
-
-    let current_block_number = web3
-        .eth()
-        .block_number()
-        .await
-        .map_err(|e| err_custom_create!("Error getting current block number: {}", e))?;
+    let last_final_block = finality.last_final_block(&web3, None).await?;
 
     let block_end = if let Some(block_end) = block_end {
-        if block_end > current_block_number.as_u64() - 100 {
+        if block_end > last_final_block {
             return Err(err_custom_create!(
-                "Block end is too close to the current block number"
+                "Block end {} is past the last final block {} ({})",
+                block_end,
+                last_final_block,
+                finality
             ));
         }
         block_end
     } else {
-        (current_block_number - 100).as_u64()
+        last_final_block
     };
 
+    scan_address_range(web3, db, address, block_start, block_end, options).await
+}
+
+/// Scan blocks from the scan pointer (or block_start for a new scan) up to block_end (exclusive).
+/// Caller is responsible for block_end being already final according to given policy.
+pub async fn scan_address_range(
+    web3: ScanWeb3,
+    db: SqlitePool,
+    address: Address,
+    block_start: u64,
+    block_end: u64,
+    options: ScanOptions,
+) -> Result<(), WebPortalError> {
+    let finality = options.finality;
     let existing_scan = get_scan(&db, format!("{:#x}", address).as_str())
         .await
         .map_err(|e| err_custom_create!("Error getting scan: {}", e))?;
 
     let existing_scan = if let Some(existing_scan) = existing_scan {
         existing_scan
+    } else if options.existing_only {
+        return Err(err_custom_create!("Scan of {:#x} not found", address));
     } else {
-        let block_info = web3
-            .eth()
-            .block(BlockId::Number(BlockNumber::Number(block_start.into())))
-            .await
-            .map_err(|e| err_custom_create!("Error getting block: {} {}", block_start, e))?
-            .ok_or(err_custom_create!("Block info not found {}", block_start))?;
-
-        let new_scan = ScanDbObj {
-            address: format!("{:#x}", address),
-            first_block_number: block_info.number.unwrap().as_u64() as i64,
-            first_block_timestamp: chrono::DateTime::from_timestamp(
-                block_info.timestamp.as_u64() as i64,
-                0,
-            )
-            .unwrap(),
-            next_block_number: block_info.number.unwrap().as_u64() as i64,
-            next_block_timestamp: chrono::DateTime::from_timestamp(
-                block_info.timestamp.as_u64() as i64,
-                0,
-            )
-            .unwrap(),
-        };
+        let new_scan = new_scan_obj(&web3, address, block_start, finality, Some(block_end)).await?;
         insert_scan(&db, &new_scan)
             .await
             .map_err(|e| err_custom_create!("Error inserting scan: {}", e))?;
@@ -74,62 +194,492 @@ pub async fn scan_address(
             .ok_or(err_custom_create!("Scan should be found now"))?
     };
 
-    let block_start = existing_scan.next_block_number as u64;
+    let mut existing_scan = existing_scan;
+    if existing_scan.finality_policy != Some(finality.to_string())
+        || existing_scan.finality_block_number != Some(block_end as i64)
+        || existing_scan.follow != options.follow
+    {
+        existing_scan.finality_policy = Some(finality.to_string());
+        existing_scan.finality_block_number = Some(block_end as i64);
+        existing_scan.follow = options.follow;
+        existing_scan = update_scan(&db, &existing_scan)
+            .await
+            .map_err(|e| err_custom_create!("Error updating scan: {}", e))?;
+    }
+
+    let options = if options.trace_mode.is_none() {
+        resolve_scan_options(&web3, options).await?
+    } else {
+        options
+    };
+    let trace_mode = options.trace_mode.unwrap_or(TraceMode::TraceTransaction);
+
+    retry_failed_blocks(&web3, &db, address, trace_mode).await?;
 
-    let number_of_blocks = block_end - block_start;
+    if block_start < existing_scan.first_block_number as u64 {
+        backfill_scan(
+            &web3,
+            &db,
+            address,
+            &mut existing_scan,
+            block_start,
+            options.discovery,
+            trace_mode,
+        )
+        .await?;
+    }
+
+    let block_start = existing_scan.next_block_number as u64;
 
-    if number_of_blocks < 1 {
+    if block_end <= block_start {
         log::info!("No blocks to scan");
         return Ok(());
     }
 
+    scan_blocks(
+        &web3,
+        &db,
+        address,
+        &mut existing_scan,
+        (block_start, block_end),
+        options.discovery,
+        trace_mode,
+        ScanDirection::Forward,
+    )
+    .await?;
+
+    log::info!("Finished");
+
+    Ok(())
+}
+
+/// Next chunk scanned backwards, ending at first_block_number, None when block_start is reached
+fn backfill_chunk(first_block_number: u64, block_start: u64) -> Option<(u64, u64)> {
+    (first_block_number > block_start).then(|| {
+        let chunk_start = std::cmp::max(
+            block_start,
+            first_block_number.saturating_sub(BACKFILL_CHUNK),
+        );
+        (chunk_start, first_block_number)
+    })
+}
+
+/// Extend existing scan to earlier blocks. Range before first_block_number is scanned
+/// in chunks, first_block_number is moved back after each chunk is done.
+async fn backfill_scan(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    current_scan: &mut ScanDbObj,
+    block_start: u64,
+    discovery: DiscoveryStrategy,
+    trace_mode: TraceMode,
+) -> Result<(), WebPortalError> {
+    while let Some((chunk_start, chunk_end)) =
+        backfill_chunk(current_scan.first_block_number as u64, block_start)
+    {
+        log::info!(
+            "Extending scan backwards with blocks {}..{}",
+            chunk_start,
+            chunk_end
+        );
+        scan_blocks(
+            web3,
+            db,
+            address,
+            current_scan,
+            (chunk_start, chunk_end),
+            discovery,
+            trace_mode,
+            ScanDirection::Backfill,
+        )
+        .await?;
+
+        let block_info = rpc_call("eth_getBlockByNumber", || {
+            web3.eth()
+                .block(BlockId::Number(BlockNumber::Number(chunk_start.into())))
+        })
+        .await
+        .map_err(|e| e.with_msg(format!("Error getting block {}", chunk_start)))?
+        .ok_or(err_custom_create!("Block info not found {}", chunk_start))?;
+        current_scan.first_block_number = chunk_start as i64;
+        current_scan.first_block_timestamp =
+            chrono::DateTime::from_timestamp(block_info.timestamp.as_u64() as i64, 0).unwrap();
+        *current_scan = update_scan(db, current_scan)
+            .await
+            .map_err(|e| err_custom_create!("Error updating scan: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Scan blocks in [range.0, range.1) with given discovery strategy
+#[allow(clippy::too_many_arguments)]
+async fn scan_blocks(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    current_scan: &mut ScanDbObj,
+    range: (u64, u64),
+    discovery: DiscoveryStrategy,
+    trace_mode: TraceMode,
+    direction: ScanDirection,
+) -> Result<(), WebPortalError> {
+    match discovery {
+        DiscoveryStrategy::BalanceProbe => {
+            scan_balance_probe(
+                web3,
+                db,
+                address,
+                current_scan,
+                range,
+                trace_mode,
+                direction,
+            )
+            .await
+        }
+        DiscoveryStrategy::TraceFilter => {
+            scan_trace_filter(
+                web3,
+                db,
+                address,
+                current_scan,
+                range,
+                trace_mode,
+                direction,
+            )
+            .await
+        }
+    }
+}
+
+/// Save block inspection result and, when scanning forward, move scan pointer to next_block
+/// in one transaction, so the pointer never disagrees with stored blocks
+async fn commit_block(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    current_scan: &mut ScanDbObj,
+    inspected: Option<(Address, u64, &InspectResult)>,
+    next_block: u64,
+    direction: ScanDirection,
+) -> Result<(), WebPortalError> {
+    // get everything from RPC first, to not keep the transaction open while waiting for node
+    let new_scan = if direction == ScanDirection::Forward {
+        let block_info = rpc_call("eth_getBlockByNumber", || {
+            web3.eth()
+                .block(BlockId::Number(BlockNumber::Number(next_block.into())))
+        })
+        .await
+        .map_err(|e| e.with_msg(format!("Error getting block {}", next_block)))?
+        .ok_or(err_custom_create!("Block info not found {}", next_block))?;
+        let mut new_scan = current_scan.clone();
+        new_scan.next_block_number = next_block as i64;
+        new_scan.next_block_timestamp =
+            chrono::DateTime::from_timestamp(block_info.timestamp.as_u64() as i64, 0).unwrap();
+        Some(new_scan)
+    } else {
+        None
+    };
+
+    let mut db_tx = db
+        .begin()
+        .await
+        .map_err(|e| err_custom_create!("Error starting transaction: {}", e))?;
+    if let Some((address, block_num, result)) = inspected {
+        save_inspect_result(&mut db_tx, address, block_num, result).await?;
+    }
+    if let Some(new_scan) = &new_scan {
+        update_scan(&mut *db_tx, new_scan)
+            .await
+            .map_err(|e| err_custom_create!("Error updating scan: {}", e))?;
+    }
+    db_tx
+        .commit()
+        .await
+        .map_err(|e| err_custom_create!("Error committing block {}: {}", next_block - 1, e))?;
+    if inspected.is_some() {
+        notify_new_deliveries();
+    }
+
+    if let Some(new_scan) = new_scan {
+        *current_scan = new_scan;
+    }
+    Ok(())
+}
+
+/// Inspect block and commit the result, failed blocks are queued for retry
+async fn inspect_and_commit_block(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    current_scan: &mut ScanDbObj,
+    block_num: u64,
+    trace_mode: TraceMode,
+    direction: ScanDirection,
+) -> Result<(), WebPortalError> {
+    let result = inspect_block(web3.clone(), address, block_num, trace_mode).await;
+    commit_block(
+        web3,
+        db,
+        current_scan,
+        Some((address, block_num, &result)),
+        block_num + 1,
+        direction,
+    )
+    .await
+}
+
+/// End of the window probed from block_num. Window ends lie on a fixed grid of
+/// FAST_FORWARD_WINDOW blocks, so they stay cached while blocks of the window are inspected.
+fn window_end(block_num: u64, block_end: u64) -> u64 {
+    std::cmp::min(
+        ((block_num + 1) / FAST_FORWARD_WINDOW + 1) * FAST_FORWARD_WINDOW,
+        block_end,
+    )
+}
+
+/// Window ends probed by scan_balance_probe from block_num on, as long as the balance stays unchanged
+fn upcoming_window_ends(block_num: u64, block_end: u64, count: usize) -> Vec<u64> {
+    let mut window_ends = Vec::new();
+    let mut block_num = block_num;
+    while window_ends.len() < count {
+        let window_end = window_end(block_num, block_end);
+        if window_end <= block_num + 1 {
+            break;
+        }
+        window_ends.push(window_end);
+        block_num = window_end - 1;
+    }
+    window_ends
+}
+
+/// Walk blocks one by one, skipping windows in which the balance did not change
+async fn scan_balance_probe(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    current_scan: &mut ScanDbObj,
+    (block_start, block_end): (u64, u64),
+    trace_mode: TraceMode,
+    direction: ScanDirection,
+) -> Result<(), WebPortalError> {
     let mut prev_checked_block = None;
     let mut block_num = block_start;
-    let mut current_scan = existing_scan;
     loop {
         if block_num >= block_end {
             break;
         }
-        if prev_checked_block.is_some() {
+        // never probe balance past block_end, it may not be final yet
+        let window_end = window_end(block_num, block_end);
+        if prev_checked_block.is_some() && window_end > block_num + 1 {
+            // get balances of the following windows in one batch, once the current one is missing
+            if !is_balance_cached(address, window_end) {
+                prefetch_balances(
+                    web3,
+                    address,
+                    &upcoming_window_ends(block_num, block_end, *RPC_BATCH_SIZE),
+                )
+                .await?;
+            }
             let prev_balance =
                 cached_get_balance(web3.clone(), address, prev_checked_block.unwrap())
                     .await
-                    .map_err(|e| err_custom_create!("Error getting balance: {}", e))?;
-            let future_50_blocks_balance =
-                cached_get_balance(web3.clone(), address, block_num + 50)
-                    .await
-                    .map_err(|e| err_custom_create!("Error getting balance: {}", e))?;
-            if future_50_blocks_balance == prev_balance {
-                log::info!("Balance did not change in 50 blocks");
-                block_num += 49;
+                    .map_err(|e| e.with_msg("Error getting balance"))?;
+            let window_end_balance = cached_get_balance(web3.clone(), address, window_end)
+                .await
+                .map_err(|e| e.with_msg("Error getting balance"))?;
+            if window_end_balance == prev_balance {
+                log::info!(
+                    "Balance did not change in {} blocks",
+                    window_end - block_num
+                );
+                save_skipped_ranges(db, address, &[(block_num, window_end - 1)]).await?;
+                block_num = window_end - 1;
                 prev_checked_block = Some(block_num);
                 continue;
             }
         }
-        match inspect_block(web3.clone(), db.clone(), address, block_num).await {
-            Ok(_) => {}
-            Err(e) => {
-                log::warn!("Error inspecting block: {}", e);
-            }
-        }
+        inspect_and_commit_block(
+            web3,
+            db,
+            address,
+            current_scan,
+            block_num,
+            trace_mode,
+            direction,
+        )
+        .await?;
         prev_checked_block = Some(block_num);
         block_num += 1;
-        current_scan.next_block_number = block_num as i64;
-        let block_info = web3
-            .eth()
-            .block(BlockId::Number(BlockNumber::Number(block_num.into())))
-            .await
-            .map_err(|e| err_custom_create!("Error getting block: {} {}", block_start, e))?
-            .ok_or(err_custom_create!("Block info not found {}", block_start))?;
+    }
+    Ok(())
+}
 
-        current_scan.next_block_timestamp =
-            chrono::DateTime::from_timestamp(block_info.timestamp.as_u64() as i64, 0).unwrap();
-        update_scan(&db, &current_scan)
-            .await
-            .map_err(|e| err_custom_create!("Error updating scan: {}", e))?;
+/// Inspect only blocks returned by trace_filter, withdrawal and fee recipient discovery
+async fn scan_trace_filter(
+    web3: &ScanWeb3,
+    db: &SqlitePool,
+    address: Address,
+    current_scan: &mut ScanDbObj,
+    (block_start, block_end): (u64, u64),
+    trace_mode: TraceMode,
+    direction: ScanDirection,
+) -> Result<(), WebPortalError> {
+    let mut chunk_start = block_start;
+    while chunk_start < block_end {
+        let chunk_end = std::cmp::min(chunk_start + TRACE_FILTER_CHUNK, block_end);
+        let blocks = discover_blocks(web3, address, chunk_start, chunk_end).await?;
+        log::info!(
+            "Found {} blocks to inspect in range {}..{}",
+            blocks.len(),
+            chunk_start,
+            chunk_end
+        );
+        let mut skipped = Vec::new();
+        let mut pos = chunk_start;
+        for block_num in &blocks {
+            if *block_num > pos {
+                skipped.push((pos, *block_num));
+            }
+            pos = block_num + 1;
+        }
+        if pos < chunk_end {
+            skipped.push((pos, chunk_end));
+        }
+        for block_num in blocks {
+            inspect_and_commit_block(
+                web3,
+                db,
+                address,
+                current_scan,
+                block_num,
+                trace_mode,
+                direction,
+            )
+            .await?;
+        }
+        save_skipped_ranges(db, address, &skipped).await?;
+        if direction == ScanDirection::Forward && current_scan.next_block_number as u64 != chunk_end
+        {
+            commit_block(web3, db, current_scan, None, chunk_end, direction).await?;
+        }
+        chunk_start = chunk_end;
     }
+    Ok(())
+}
 
-    log::info!("Finished");
+#[test]
+fn upcoming_window_ends_test() {
+    assert_eq!(upcoming_window_ends(100, 300, 3), vec![150, 200, 250]);
+    assert_eq!(upcoming_window_ends(100, 210, 10), vec![150, 200, 210]);
+    assert_eq!(upcoming_window_ends(209, 210, 10), Vec::<u64>::new());
+    // inspecting blocks one by one keeps probing the same window end
+    assert_eq!(window_end(101, 300), 150);
+    assert_eq!(window_end(148, 300), 150);
+    assert_eq!(window_end(149, 300), 200);
+}
 
-    Ok(())
+#[test]
+fn backfill_chunk_test() {
+    let mut chunks = Vec::new();
+    let mut first_block_number = 12_500;
+    while let Some((chunk_start, chunk_end)) = backfill_chunk(first_block_number, 10_000) {
+        chunks.push((chunk_start, chunk_end));
+        first_block_number = chunk_start;
+    }
+    assert_eq!(
+        chunks,
+        vec![(11_500, 12_500), (10_500, 11_500), (10_000, 10_500)]
+    );
+    assert_eq!(backfill_chunk(500, 0), Some((0, 500)));
+    assert_eq!(backfill_chunk(10_000, 10_000), None);
+    assert_eq!(backfill_chunk(10_000, 12_000), None);
+}
+
+#[cfg(test)]
+fn block_timestamps_web3() -> ScanWeb3 {
+    // written by hand, blocks 0..=16 with timestamps 1700000000 + 12 * number
+    web3::Web3::new(
+        ScanTransport::replay(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/rpc/block_timestamps.ndjson"
+        )))
+        .unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn find_block_by_timestamp_test() {
+    let web3 = block_timestamps_web3();
+    let at = |timestamp| chrono::DateTime::from_timestamp(timestamp, 0).unwrap();
+    assert_eq!(find_block_by_timestamp(&web3, at(0)).await.unwrap(), 0);
+    assert_eq!(
+        find_block_by_timestamp(&web3, at(1700000060))
+            .await
+            .unwrap(),
+        5
+    );
+    assert_eq!(
+        find_block_by_timestamp(&web3, at(1700000061))
+            .await
+            .unwrap(),
+        6
+    );
+    assert_eq!(
+        find_block_by_timestamp(&web3, at(1700000192))
+            .await
+            .unwrap(),
+        16
+    );
+    assert!(find_block_by_timestamp(&web3, at(1700000193))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn new_scan_obj_test() {
+    let web3 = block_timestamps_web3();
+    let scan = new_scan_obj(
+        &web3,
+        Address::from_low_u64_be(1),
+        3,
+        FinalityPolicy::default(),
+        Some(10),
+    )
+    .await
+    .unwrap();
+    assert_eq!(scan.address, "0x0000000000000000000000000000000000000001");
+    assert_eq!((scan.first_block_number, scan.next_block_number), (3, 3));
+    assert_eq!(scan.first_block_timestamp.timestamp(), 1700000036);
+    assert_eq!(scan.next_block_timestamp, scan.first_block_timestamp);
+    assert_eq!(
+        scan.finality_policy,
+        Some(FinalityPolicy::default().to_string())
+    );
+    assert_eq!(scan.finality_block_number, Some(10));
+}
+
+#[tokio::test]
+async fn scan_existing_only_test() {
+    let conn = crate::create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+    let address = Address::from_low_u64_be(1);
+    let options = ScanOptions {
+        existing_only: true,
+        ..Default::default()
+    };
+    let res = scan_address_range(
+        block_timestamps_web3(),
+        conn.clone(),
+        address,
+        3,
+        10,
+        options,
+    )
+    .await;
+    assert!(res.is_err());
+    assert!(get_scan(&conn, &format!("{:#x}", address))
+        .await
+        .unwrap()
+        .is_none());
 }
@@ -0,0 +1,313 @@
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::scan::capabilities::TraceMode;
+use crate::scan::rpc::rpc_call;
+use crate::scan::transport::ScanWeb3;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use web3::types::{Action, Address, BlockNumber, Trace, Transaction, U256};
+use web3::Transport;
+
+/// Call moving value between two addresses, taken from traces or receipts
+#[derive(Debug, Clone)]
+pub struct CallTrace {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U256,
+}
+
+/// Calls of one block grouped by transaction position
+pub type BlockTraces = HashMap<usize, Vec<CallTrace>>;
+
+/// Number of blocks kept in the trace cache
+const TRACE_CACHE_BLOCKS: usize = 200;
+
+lazy_static! {
+    static ref CACHE: Mutex<BTreeMap<u64, Arc<BlockTraces>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Put traces of the block into the cache, dropping the lowest blocks above TRACE_CACHE_BLOCKS
+fn insert_cached(
+    cache: &mut BTreeMap<u64, Arc<BlockTraces>>,
+    block_num: u64,
+    traces: Arc<BlockTraces>,
+) {
+    cache.insert(block_num, traces);
+    while cache.len() > TRACE_CACHE_BLOCKS {
+        cache.pop_first();
+    }
+}
+
+fn calls_from_traces(traces: Vec<Trace>) -> Result<Vec<CallTrace>, WebPortalError> {
+    let mut calls = Vec::new();
+    for trace in traces {
+        match trace.action {
+            Action::Call(call) => calls.push(CallTrace {
+                from: call.from,
+                to: call.to,
+                value: call.value,
+                gas: call.gas,
+            }),
+            Action::Reward(_) => {
+                return Err(err_custom_create!("Reward action found"));
+            }
+            _ => {
+                log::debug!("Unknown action: {:?}", trace.action);
+            }
+        }
+    }
+    Ok(calls)
+}
+
+fn group_by_tx_position(traces: Vec<Trace>) -> Result<BlockTraces, WebPortalError> {
+    let mut grouped: HashMap<usize, Vec<Trace>> = HashMap::new();
+    for trace in traces {
+        // block and uncle rewards have no transaction position
+        if let Some(position) = trace.transaction_position {
+            grouped.entry(position).or_default().push(trace);
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|(position, traces)| Ok((position, calls_from_traces(traces)?)))
+        .collect()
+}
+
+async fn get_block_traces_per_tx(
+    web3: &ScanWeb3,
+    txs: &[Transaction],
+) -> Result<BlockTraces, WebPortalError> {
+    let mut grouped = BlockTraces::new();
+    for (position, tx) in txs.iter().enumerate() {
+        let traces = rpc_call("trace_transaction", || web3.trace().transaction(tx.hash))
+            .await
+            .map_err(|e| e.with_msg("Error getting traces"))?;
+        grouped.insert(position, calls_from_traces(traces)?);
+    }
+    Ok(grouped)
+}
+
+/// Frame returned by debug_traceTransaction with callTracer
+#[derive(Deserialize, Debug)]
+struct CallFrame {
+    #[serde(rename = "type")]
+    call_type: String,
+    from: Address,
+    to: Option<Address>,
+    value: Option<U256>,
+    gas: Option<U256>,
+    error: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+}
+
+/// Flatten call tree in the same order as trace_transaction returns it
+fn flatten_call_frame(frame: CallFrame, calls: &mut Vec<CallTrace>) {
+    // reverted frame does not move any value, neither do its subcalls
+    if frame.error.is_some() {
+        return;
+    }
+    if let Some(to) = frame.to {
+        let value = match frame.call_type.as_str() {
+            // created contract and selfdestruct beneficiary receive the value as well
+            "CALL" | "CREATE" | "CREATE2" | "SELFDESTRUCT" => frame.value.unwrap_or_default(),
+            // delegate and static calls report value of the parent context, not a transfer
+            "CALLCODE" | "DELEGATECALL" | "STATICCALL" => U256::zero(),
+            _ => {
+                log::debug!("Skipping {} frame", frame.call_type);
+                U256::zero()
+            }
+        };
+        calls.push(CallTrace {
+            from: frame.from,
+            to,
+            value,
+            gas: frame.gas.unwrap_or_default(),
+        });
+    }
+    for subcall in frame.calls {
+        flatten_call_frame(subcall, calls);
+    }
+}
+
+async fn get_block_traces_debug(
+    web3: &ScanWeb3,
+    txs: &[Transaction],
+) -> Result<BlockTraces, WebPortalError> {
+    let mut grouped = BlockTraces::new();
+    for (position, tx) in txs.iter().enumerate() {
+        let res = rpc_call("debug_traceTransaction", || {
+            web3.transport().execute(
+                "debug_traceTransaction",
+                vec![json!(tx.hash), json!({"tracer": "callTracer"})],
+            )
+        })
+        .await
+        .map_err(|e| e.with_msg("Error getting debug trace"))?;
+        let frame: CallFrame = serde_json::from_value(res)
+            .map_err(|e| err_custom_create!("Error parsing debug trace: {}", e))?;
+        let mut calls = Vec::new();
+        flatten_call_frame(frame, &mut calls);
+        grouped.insert(position, calls);
+    }
+    Ok(grouped)
+}
+
+/// Top level transfers of successful transactions sent from or to the address
+async fn get_block_transfers_from_receipts(
+    web3: &ScanWeb3,
+    txs: &[Transaction],
+    address: Address,
+) -> Result<BlockTraces, WebPortalError> {
+    let mut grouped = BlockTraces::new();
+    for (position, tx) in txs.iter().enumerate() {
+        let (Some(from), Some(to)) = (tx.from, tx.to) else {
+            continue;
+        };
+        if from != address && to != address {
+            continue;
+        }
+        let receipt = rpc_call("eth_getTransactionReceipt", || {
+            web3.eth().transaction_receipt(tx.hash)
+        })
+        .await
+        .map_err(|e| e.with_msg("Error getting receipt"))?
+        .ok_or(err_custom_create!("Receipt not found {:#x}", tx.hash))?;
+        if receipt.status != Some(1.into()) {
+            continue;
+        }
+        grouped.insert(
+            position,
+            vec![CallTrace {
+                from,
+                to,
+                value: tx.value,
+                gas: tx.gas,
+            }],
+        );
+    }
+    Ok(grouped)
+}
+
+/// Get value moving calls of all transactions in the block using given trace mode.
+/// trace_block falls back to trace_transaction when it fails for a block.
+/// Receipt only mode returns transfers touching the address and is not cached.
+pub async fn cached_get_block_traces(
+    web3: ScanWeb3,
+    block_num: u64,
+    txs: &[Transaction],
+    address: Address,
+    trace_mode: TraceMode,
+) -> Result<Arc<BlockTraces>, WebPortalError> {
+    if trace_mode == TraceMode::ReceiptOnly {
+        return Ok(Arc::new(
+            get_block_transfers_from_receipts(&web3, txs, address).await?,
+        ));
+    }
+
+    let traces_from_cache = {
+        let cache = CACHE.lock().unwrap();
+        cache.get(&block_num).cloned()
+    };
+    if let Some(traces) = traces_from_cache {
+        return Ok(traces);
+    }
+
+    let block_traces = match trace_mode {
+        TraceMode::TraceBlock => {
+            match rpc_call("trace_block", || {
+                web3.trace().block(BlockNumber::Number(block_num.into()))
+            })
+            .await
+            {
+                Ok(traces) => group_by_tx_position(traces)?,
+                Err(e) => {
+                    log::warn!(
+                        "trace_block failed for block {}, falling back to trace_transaction: {}",
+                        block_num,
+                        e
+                    );
+                    get_block_traces_per_tx(&web3, txs).await?
+                }
+            }
+        }
+        TraceMode::TraceTransaction => get_block_traces_per_tx(&web3, txs).await?,
+        TraceMode::DebugTrace => get_block_traces_debug(&web3, txs).await?,
+        TraceMode::ReceiptOnly => unreachable!("receipt only mode is handled above"),
+    };
+
+    let block_traces = Arc::new(block_traces);
+    insert_cached(&mut CACHE.lock().unwrap(), block_num, block_traces.clone());
+    Ok(block_traces)
+}
+
+#[test]
+fn flatten_call_frame_test() {
+    let frame: CallFrame = serde_json::from_value(json!({
+        "type": "CALL",
+        "from": "0x0000000000000000000000000000000000000001",
+        "to": "0x0000000000000000000000000000000000000002",
+        "value": "0x10",
+        "gas": "0x5208",
+        "calls": [
+            {
+                "type": "DELEGATECALL",
+                "from": "0x0000000000000000000000000000000000000002",
+                "to": "0x0000000000000000000000000000000000000003",
+                "value": "0x10",
+                "calls": [{
+                    "type": "CALL",
+                    "from": "0x0000000000000000000000000000000000000002",
+                    "to": "0x0000000000000000000000000000000000000004",
+                    "value": "0x5"
+                }]
+            },
+            {
+                "type": "CALL",
+                "from": "0x0000000000000000000000000000000000000002",
+                "to": "0x0000000000000000000000000000000000000005",
+                "value": "0x3",
+                "error": "execution reverted"
+            },
+            {
+                "type": "CREATE2",
+                "from": "0x0000000000000000000000000000000000000002",
+                "to": "0x0000000000000000000000000000000000000006",
+                "value": "0x7",
+                "calls": [{
+                    "type": "SELFDESTRUCT",
+                    "from": "0x0000000000000000000000000000000000000006",
+                    "to": "0x0000000000000000000000000000000000000001",
+                    "value": "0x7"
+                }]
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut calls = Vec::new();
+    flatten_call_frame(frame, &mut calls);
+
+    let values: Vec<u64> = calls.iter().map(|c| c.value.as_u64()).collect();
+    assert_eq!(values, vec![16, 0, 5, 7, 7]);
+    assert_eq!(calls[2].to, Address::from_low_u64_be(4));
+}
+
+#[test]
+fn insert_cached_test() {
+    let mut cache = BTreeMap::new();
+    for block_num in (0..TRACE_CACHE_BLOCKS as u64 + 10).rev() {
+        insert_cached(&mut cache, block_num, Arc::new(BlockTraces::new()));
+    }
+    assert_eq!(cache.len(), TRACE_CACHE_BLOCKS);
+    assert_eq!(cache.keys().next(), Some(&10));
+
+    insert_cached(&mut cache, 5, Arc::new(BlockTraces::new()));
+    assert_eq!(cache.len(), TRACE_CACHE_BLOCKS);
+    assert!(!cache.contains_key(&5));
+    assert_eq!(cache.keys().last(), Some(&(TRACE_CACHE_BLOCKS as u64 + 9)));
+}
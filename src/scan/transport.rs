@@ -0,0 +1,236 @@
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use futures_util::future::BoxFuture;
+use jsonrpc_core::{Call, Params, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use web3::transports::Http;
+use web3::{BatchTransport, RequestId, Transport};
+
+/// Web3 client used by the scanner
+pub type ScanWeb3 = web3::Web3<ScanTransport>;
+
+/// One JSON-RPC call with its answer, a line of the fixture file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RecordedCall {
+    method: String,
+    params: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<jsonrpc_core::Error>,
+}
+
+impl RecordedCall {
+    fn key(&self) -> String {
+        call_key(&self.method, &self.params)
+    }
+
+    fn response(&self) -> web3::error::Result<Value> {
+        match &self.error {
+            Some(e) => Err(web3::Error::Rpc(e.clone())),
+            None => Ok(self.result.clone().unwrap_or(Value::Null)),
+        }
+    }
+}
+
+fn call_key(method: &str, params: &[Value]) -> String {
+    format!(
+        "{}{}",
+        method,
+        serde_json::to_string(params).unwrap_or_default()
+    )
+}
+
+fn method_and_params(call: &Call) -> (String, Vec<Value>) {
+    match call {
+        Call::MethodCall(call) => {
+            let params = match &call.params {
+                Params::Array(params) => params.clone(),
+                Params::Map(map) => vec![Value::Object(map.clone())],
+                Params::None => Vec::new(),
+            };
+            (call.method.clone(), params)
+        }
+        _ => (String::new(), Vec::new()),
+    }
+}
+
+/// Appends every answered call to the fixture file, one JSON object per line
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<std::fs::File>,
+}
+
+impl Recorder {
+    fn record(&self, call: &Call, response: &web3::error::Result<Value>) {
+        let (method, params) = method_and_params(call);
+        let recorded = match response {
+            Ok(result) => RecordedCall {
+                method,
+                params,
+                result: Some(result.clone()),
+                error: None,
+            },
+            Err(web3::Error::Rpc(e)) => RecordedCall {
+                method,
+                params,
+                result: None,
+                error: Some(e.clone()),
+            },
+            // transport failures are retried, only the final answer is interesting
+            Err(_) => return,
+        };
+        let line = serde_json::to_string(&recorded).unwrap_or_default();
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            log::error!("Error writing RPC recording: {}", e);
+        }
+    }
+}
+
+/// Answers calls from a fixture file. Calls repeated with the same params get the recorded
+/// answers in order, the last one is repeated when they run out.
+#[derive(Debug)]
+pub struct Replay {
+    calls: Mutex<HashMap<String, VecDeque<RecordedCall>>>,
+    next_id: AtomicUsize,
+}
+
+impl Replay {
+    fn answer(&self, call: &Call) -> web3::error::Result<Value> {
+        let (method, params) = method_and_params(call);
+        let mut calls = self.calls.lock().unwrap();
+        let answers = calls.get_mut(&call_key(&method, &params)).ok_or_else(|| {
+            web3::Error::InvalidResponse(format!(
+                "No recorded response for {} {}",
+                method,
+                serde_json::to_string(&params).unwrap_or_default()
+            ))
+        })?;
+        let recorded = if answers.len() > 1 {
+            answers.pop_front().unwrap()
+        } else {
+            answers[0].clone()
+        };
+        recorded.response()
+    }
+}
+
+/// Transport used by the scanner: HTTP, HTTP saving all calls to a fixture file,
+/// or replay of such file without any node
+#[derive(Debug, Clone)]
+pub enum ScanTransport {
+    Http(Http),
+    Recording(Http, Arc<Recorder>),
+    Replay(Arc<Replay>),
+}
+
+impl ScanTransport {
+    pub fn recording(http: Http, path: &Path) -> Result<ScanTransport, WebPortalError> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| err_custom_create!("Error opening {}: {}", path.display(), e))?;
+        log::info!("Recording RPC calls to {}", path.display());
+        Ok(ScanTransport::Recording(
+            http,
+            Arc::new(Recorder {
+                file: Mutex::new(file),
+            }),
+        ))
+    }
+
+    pub fn replay(path: &Path) -> Result<ScanTransport, WebPortalError> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| err_custom_create!("Error opening {}: {}", path.display(), e))?;
+        let mut calls: HashMap<String, VecDeque<RecordedCall>> = HashMap::new();
+        for line in std::io::BufReader::new(file).lines() {
+            let line =
+                line.map_err(|e| err_custom_create!("Error reading {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let recorded: RecordedCall = serde_json::from_str(&line)
+                .map_err(|e| err_custom_create!("Invalid recorded call {}: {}", line, e))?;
+            calls.entry(recorded.key()).or_default().push_back(recorded);
+        }
+        log::info!("Replaying RPC calls from {}", path.display());
+        Ok(ScanTransport::Replay(Arc::new(Replay {
+            calls: Mutex::new(calls),
+            next_id: AtomicUsize::new(1),
+        })))
+    }
+}
+
+impl Transport for ScanTransport {
+    type Out = BoxFuture<'static, web3::error::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        match self {
+            ScanTransport::Http(http) | ScanTransport::Recording(http, _) => {
+                http.prepare(method, params)
+            }
+            ScanTransport::Replay(replay) => {
+                let id = replay.next_id.fetch_add(1, Ordering::Relaxed);
+                (id, web3::helpers::build_request(id, method, params))
+            }
+        }
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        match self {
+            ScanTransport::Http(http) => http.send(id, request),
+            ScanTransport::Recording(http, recorder) => {
+                let response = http.send(id, request.clone());
+                let recorder = recorder.clone();
+                Box::pin(async move {
+                    let response = response.await;
+                    recorder.record(&request, &response);
+                    response
+                })
+            }
+            ScanTransport::Replay(replay) => {
+                let response = replay.answer(&request);
+                Box::pin(async move { response })
+            }
+        }
+    }
+}
+
+impl BatchTransport for ScanTransport {
+    type Batch = BoxFuture<'static, web3::error::Result<Vec<web3::error::Result<Value>>>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        match self {
+            ScanTransport::Http(http) => http.send_batch(requests),
+            ScanTransport::Recording(http, recorder) => {
+                let requests: Vec<_> = requests.into_iter().collect();
+                let responses = http.send_batch(requests.clone());
+                let recorder = recorder.clone();
+                Box::pin(async move {
+                    let responses = responses.await?;
+                    for ((_, request), response) in requests.iter().zip(&responses) {
+                        recorder.record(request, response);
+                    }
+                    Ok(responses)
+                })
+            }
+            ScanTransport::Replay(replay) => {
+                let responses = requests
+                    .into_iter()
+                    .map(|(_, request)| replay.answer(&request))
+                    .collect();
+                Box::pin(async move { Ok(responses) })
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::db::model::transaction::{wei, BlockDbObj};
+use crate::db::ops::transaction::{stream_blocks, BlockFilter};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Day,
+    /// Weeks start on Monday
+    Week,
+    #[default]
+    Month,
+    Year,
+}
+
+/// Sums of blocks in one bucket, wei amounts are decimal strings like in the blocks API
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketSummary {
+    /// Local start of the bucket
+    pub start: DateTime<FixedOffset>,
+    pub blocks: usize,
+    pub opening_balance: String,
+    pub closing_balance: String,
+    pub balance_diff: String,
+    pub consensus_reward: String,
+    pub mev_reward: String,
+    pub block_reward: String,
+    pub amount_incoming: String,
+    pub amount_outgoing: String,
+}
+
+/// Local date the bucket of given time starts on
+fn bucket_start_date(timestamp: DateTime<Utc>, bucket: Bucket, tz: Tz) -> NaiveDate {
+    let date = timestamp.with_timezone(&tz).date_naive();
+    match bucket {
+        Bucket::Day => date,
+        Bucket::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+        Bucket::Month => date.with_day(1).unwrap_or(date),
+        Bucket::Year => date.with_ordinal(1).unwrap_or(date),
+    }
+}
+
+fn local_midnight(date: NaiveDate, tz: Tz) -> DateTime<FixedOffset> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    // midnight can be skipped by a DST change, then the bucket starts at the first hour that exists
+    (0..=3)
+        .find_map(|hours| {
+            tz.from_local_datetime(&(midnight + chrono::Duration::hours(hours)))
+                .earliest()
+        })
+        .unwrap_or_else(|| tz.from_utc_datetime(&midnight))
+        .fixed_offset()
+}
+
+#[derive(Debug)]
+struct BucketSums {
+    start: NaiveDate,
+    blocks: usize,
+    opening_balance: i128,
+    closing_balance: i128,
+    balance_diff: i128,
+    consensus_reward: i128,
+    mev_reward: i128,
+    block_reward: i128,
+    amount_incoming: i128,
+    amount_outgoing: i128,
+}
+
+impl BucketSums {
+    fn new(start: NaiveDate, block: &BlockDbObj) -> Self {
+        BucketSums {
+            start,
+            blocks: 0,
+            opening_balance: wei(&block.balance) - wei(&block.balance_diff),
+            closing_balance: 0,
+            balance_diff: 0,
+            consensus_reward: 0,
+            mev_reward: 0,
+            block_reward: 0,
+            amount_incoming: 0,
+            amount_outgoing: 0,
+        }
+    }
+
+    fn add(&mut self, block: &BlockDbObj) {
+        self.blocks += 1;
+        self.closing_balance = wei(&block.balance);
+        self.balance_diff += wei(&block.balance_diff);
+        self.consensus_reward += wei(&block.consensus_reward);
+        self.mev_reward += wei(&block.mev_reward);
+        self.block_reward += wei(&block.block_reward);
+        self.amount_incoming += wei(&block.amount_incoming);
+        self.amount_outgoing += wei(&block.amount_outgoing);
+    }
+
+    fn finish(self, tz: Tz) -> BucketSummary {
+        BucketSummary {
+            start: local_midnight(self.start, tz),
+            blocks: self.blocks,
+            opening_balance: self.opening_balance.to_string(),
+            closing_balance: self.closing_balance.to_string(),
+            balance_diff: self.balance_diff.to_string(),
+            consensus_reward: self.consensus_reward.to_string(),
+            mev_reward: self.mev_reward.to_string(),
+            block_reward: self.block_reward.to_string(),
+            amount_incoming: self.amount_incoming.to_string(),
+            amount_outgoing: self.amount_outgoing.to_string(),
+        }
+    }
+}
+
+/// Sums blocks ordered by block number into buckets, buckets without blocks are left out
+struct Summarizer {
+    bucket: Bucket,
+    tz: Tz,
+    current: Option<BucketSums>,
+    done: Vec<BucketSummary>,
+}
+
+impl Summarizer {
+    fn new(bucket: Bucket, tz: Tz) -> Self {
+        Summarizer {
+            bucket,
+            tz,
+            current: None,
+            done: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, block: &BlockDbObj) {
+        let start = bucket_start_date(block.timestamp, self.bucket, self.tz);
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|sums| sums.start != start)
+        {
+            if let Some(sums) = self.current.take() {
+                self.done.push(sums.finish(self.tz));
+            }
+        }
+        self.current
+            .get_or_insert_with(|| BucketSums::new(start, block))
+            .add(block);
+    }
+
+    fn finish(mut self) -> Vec<BucketSummary> {
+        if let Some(sums) = self.current.take() {
+            self.done.push(sums.finish(self.tz));
+        }
+        self.done
+    }
+}
+
+/// Parse IANA time zone name, like `Europe/Warsaw`
+pub fn parse_tz(name: &str) -> Result<Tz, WebPortalError> {
+    name.parse()
+        .map_err(|_| err_custom_create!("Unknown time zone {}", name))
+}
+
+/// Blocks of the address summed per bucket of local time, blocks are streamed from database
+pub async fn get_summary(
+    conn: &SqlitePool,
+    address: &str,
+    bucket: Bucket,
+    tz: Tz,
+) -> Result<Vec<BucketSummary>, WebPortalError> {
+    let mut summarizer = Summarizer::new(bucket, tz);
+    let mut blocks = stream_blocks(conn, address, &BlockFilter::default());
+    while let Some(block) = blocks.next().await {
+        let block = block.map_err(|e| err_custom_create!("Error reading blocks: {}", e))?;
+        summarizer.add(&block);
+    }
+    Ok(summarizer.finish())
+}
+
+#[test]
+fn summarize_test() {
+    let block = |timestamp: &str, balance: i128, diff: i128| BlockDbObj {
+        balance: balance.to_string(),
+        balance_diff: diff.to_string(),
+        consensus_reward: diff.max(0).to_string(),
+        amount_outgoing: (-diff).max(0).to_string(),
+        ..BlockDbObj::test_block("0x01", 0, timestamp)
+    };
+    let blocks = [
+        block("2024-01-10T12:00:00Z", 110, 10),
+        // already February in Warsaw
+        block("2024-01-31T23:30:00Z", 130, 20),
+        block("2024-02-20T12:00:00Z", 100, -30),
+    ];
+
+    let summarize = |bucket, tz| {
+        let mut summarizer = Summarizer::new(bucket, parse_tz(tz).unwrap());
+        blocks.iter().for_each(|block| summarizer.add(block));
+        summarizer.finish()
+    };
+    let utc = summarize(Bucket::Month, "UTC");
+    assert_eq!(utc.len(), 2);
+    assert_eq!(utc[0].blocks, 2);
+    assert_eq!(utc[0].opening_balance, "100");
+    assert_eq!(utc[0].closing_balance, "130");
+
+    let warsaw = summarize(Bucket::Month, "Europe/Warsaw");
+    assert_eq!(warsaw[1].start.to_rfc3339(), "2024-02-01T00:00:00+01:00");
+    assert_eq!(warsaw[1].blocks, 2);
+    assert_eq!(warsaw[1].opening_balance, "110");
+    assert_eq!(warsaw[1].balance_diff, "-10");
+    assert_eq!(warsaw[1].consensus_reward, "20");
+    assert_eq!(warsaw[1].amount_outgoing, "30");
+
+    let weeks = summarize(Bucket::Week, "UTC");
+    assert_eq!(weeks[0].start.to_rfc3339(), "2024-01-08T00:00:00+00:00");
+    assert_eq!(summarize(Bucket::Year, "UTC").len(), 1);
+    assert!(parse_tz("Mars/Olympus").is_err());
+
+    // clocks in Santiago jump from midnight to 01:00 on 2024-09-08
+    let santiago = parse_tz("America/Santiago").unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 9, 8).unwrap();
+    assert_eq!(
+        local_midnight(date, santiago).to_rfc3339(),
+        "2024-09-08T01:00:00-03:00"
+    );
+}
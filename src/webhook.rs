@@ -0,0 +1,640 @@
+use crate::db::model::transaction::{wei, BlockDbObj};
+use crate::db::model::webhook::{WebhookDbObj, WebhookDeliveryDbObj};
+use crate::db::ops::webhook::{
+    get_due_webhook_deliveries, get_webhook, get_webhooks, insert_webhook_delivery,
+    update_webhook_delivery,
+};
+use crate::err_custom_create;
+use crate::error::WebPortalError;
+use crate::metrics;
+use crate::scan::block::InspectedBlock;
+use actix_tls::connect::Resolve;
+use actix_web::http::Uri;
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use rustc_hex::ToHex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Delay before the first retry, doubled with every failed attempt
+const RETRY_BASE_DELAY_SECS: i64 = 30;
+/// Retries are never delayed more than that
+const RETRY_MAX_DELAY_SECS: i64 = 6 * 3600;
+/// Delivery is given up after that many attempts
+const MAX_ATTEMPTS: i64 = 12;
+/// Deliveries sent in one round of the worker
+const DELIVERY_BATCH: i64 = 100;
+/// Deliveries of one round sent at the same time, so a slow endpoint does not hold up the rest
+const DELIVERY_CONCURRENCY: usize = 10;
+/// How often the worker looks for due retries when nothing new was queued
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header with `sha256=<hex HMAC of the body>`, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+lazy_static! {
+    static ref NEW_DELIVERIES: Notify = Notify::new();
+    /// Comma separated hosts allowed even though they resolve to loopback or private addresses
+    static ref WEBHOOK_ALLOWED_HOSTS: Vec<String> = std::env::var("WEBHOOK_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Consensus layer withdrawal credited to the address
+    Withdrawal,
+    MevPayment,
+    /// Incoming transfers of the block at or above min_transfer
+    LargeTransfer,
+    /// Found transfers do not add up to the balance change, amount is the unexplained wei
+    ReconciliationFailed,
+}
+
+impl Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookEvent::Withdrawal => write!(f, "withdrawal"),
+            WebhookEvent::MevPayment => write!(f, "mev_payment"),
+            WebhookEvent::LargeTransfer => write!(f, "large_transfer"),
+            WebhookEvent::ReconciliationFailed => write!(f, "reconciliation_failed"),
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "withdrawal" => Ok(WebhookEvent::Withdrawal),
+            "mev_payment" => Ok(WebhookEvent::MevPayment),
+            "large_transfer" => Ok(WebhookEvent::LargeTransfer),
+            "reconciliation_failed" => Ok(WebhookEvent::ReconciliationFailed),
+            other => Err(format!("Unknown webhook event {other}")),
+        }
+    }
+}
+
+/// Subscription as sent by the client, amounts in wei
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookInput {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    #[serde(default)]
+    pub min_transfer: Option<String>,
+    #[serde(default)]
+    pub min_reward: Option<String>,
+    /// Generated when not given
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// Body of the webhook request
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayload {
+    /// Same for every retry, so receivers can drop duplicates
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub address: String,
+    pub block_number: i64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Wei amount that triggered the event
+    pub amount: Option<String>,
+    pub block: BlockDbObj,
+}
+
+/// Address reachable from the internet, webhooks must not be sent into the local network
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                // shared address space of carrier grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local fc00::/7 and link local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Host and port of the webhook url, checked to be http(s) and not a local host
+fn webhook_host(url: &str) -> Result<(String, u16), WebPortalError> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|e| err_custom_create!("Invalid webhook url {}: {}", url, e))?;
+    let port = match uri.scheme_str() {
+        Some("http") => 80,
+        Some("https") => 443,
+        _ => return Err(err_custom_create!("Webhook url has to be http or https")),
+    };
+    let host = uri
+        .host()
+        .ok_or(err_custom_create!("Webhook url has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    if !WEBHOOK_ALLOWED_HOSTS.contains(&host) {
+        let local = match host.parse::<IpAddr>() {
+            Ok(ip) => !is_public_ip(ip),
+            Err(_) => host == "localhost" || host.ends_with(".localhost"),
+        };
+        if local {
+            return Err(err_custom_create!("Webhook host {} is not public", host));
+        }
+    }
+    Ok((host, uri.port_u16().unwrap_or(port)))
+}
+
+/// Resolves webhook hosts for the HTTP client. Connections go only to the addresses
+/// checked here, so a name rebinding to a local address after the check is refused too.
+struct PublicResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn lookup<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, Result<Vec<SocketAddr>, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+            if !self.allowed_hosts.contains(&host.to_lowercase()) {
+                if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                    return Err(format!("Webhook host {} resolves to {}", host, addr.ip()).into());
+                }
+            }
+            Ok(addrs)
+        })
+    }
+}
+
+/// Client not following redirects, they could point to a local host
+fn webhook_client(allowed_hosts: Vec<String>) -> awc::Client {
+    awc::Client::builder()
+        .disable_redirects()
+        .connector(awc::Connector::new().resolver(PublicResolver { allowed_hosts }))
+        .finish()
+}
+
+pub fn webhook_from_input(
+    address: &str,
+    input: WebhookInput,
+) -> Result<WebhookDbObj, WebPortalError> {
+    webhook_host(&input.url)?;
+    if input.events.is_empty() {
+        return Err(err_custom_create!("No webhook events given"));
+    }
+    let amount = |value: Option<String>| -> Result<String, WebPortalError> {
+        let value = value.unwrap_or("0".to_string());
+        value
+            .parse::<u128>()
+            .map(|amount| amount.to_string())
+            .map_err(|_| err_custom_create!("Invalid wei amount {}", value))
+    };
+    let mut events: Vec<WebhookEvent> = Vec::new();
+    for event in input.events {
+        if !events.contains(&event) {
+            events.push(event);
+        }
+    }
+    Ok(WebhookDbObj {
+        id: uuid::Uuid::new_v4().to_string(),
+        address: address.to_lowercase(),
+        url: input.url,
+        secret: input.secret.filter(|s| !s.is_empty()).unwrap_or_else(|| {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret.to_hex()
+        }),
+        events: events
+            .iter()
+            .map(|event| event.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        min_transfer: amount(input.min_transfer)?,
+        min_reward: amount(input.min_reward)?,
+        created: chrono::Utc::now(),
+    })
+}
+
+/// Events of the inspected block the webhook is subscribed to, with amounts that triggered them
+fn block_events(
+    webhook: &WebhookDbObj,
+    inspected: &InspectedBlock,
+) -> Vec<(WebhookEvent, Option<String>)> {
+    let block = &inspected.block;
+    // backfills and rescans save old blocks again, same rule as mail alerts
+    if block.timestamp <= webhook.created {
+        return Vec::new();
+    }
+    let min_reward = wei(&webhook.min_reward);
+    let reward_event = |amount: &str| {
+        let amount = wei(amount);
+        (amount > 0 && amount >= min_reward).then(|| Some(amount.to_string()))
+    };
+    webhook
+        .events
+        .split(',')
+        .filter_map(|event| event.parse().ok())
+        .filter_map(|event| {
+            let amount = match event {
+                WebhookEvent::Withdrawal => reward_event(&block.consensus_reward)?,
+                WebhookEvent::MevPayment => reward_event(&block.mev_reward)?,
+                WebhookEvent::LargeTransfer => {
+                    let amount = wei(&block.amount_incoming);
+                    if amount == 0 || amount < wei(&webhook.min_transfer) {
+                        return None;
+                    }
+                    Some(amount.to_string())
+                }
+                WebhookEvent::ReconciliationFailed => {
+                    if block.reconciled {
+                        return None;
+                    }
+                    block.unexplained_value.clone()
+                }
+            };
+            Some((event, amount))
+        })
+        .collect()
+}
+
+/// Queue deliveries for all webhooks of the address interested in the block.
+/// Pass the transaction saving the block, so events are queued only for committed blocks.
+/// Saving the block again does not queue the same event twice.
+pub async fn queue_block_events(
+    conn: &mut SqliteConnection,
+    inspected: &InspectedBlock,
+) -> Result<(), WebPortalError> {
+    let webhooks = get_webhooks(&mut *conn, &inspected.block.address)
+        .await
+        .map_err(|e| err_custom_create!("Error getting webhooks: {}", e))?;
+    for webhook in webhooks {
+        for (event, amount) in block_events(&webhook, inspected) {
+            let payload = WebhookPayload {
+                delivery_id: format!("{}:{}:{}", webhook.id, inspected.block.block_number, event),
+                event,
+                address: inspected.block.address.clone(),
+                block_number: inspected.block.block_number,
+                timestamp: inspected.block.timestamp,
+                amount,
+                block: inspected.block.clone(),
+            };
+            let now = chrono::Utc::now();
+            let delivery = WebhookDeliveryDbObj {
+                id: payload.delivery_id.clone(),
+                webhook_id: webhook.id.clone(),
+                event: event.to_string(),
+                payload: serde_json::to_string(&payload)
+                    .map_err(|e| err_custom_create!("Error serializing webhook payload: {}", e))?,
+                status: "pending".to_string(),
+                attempts: 0,
+                last_error: None,
+                next_attempt: now,
+                created: now,
+            };
+            insert_webhook_delivery(&mut *conn, &delivery)
+                .await
+                .map_err(|e| err_custom_create!("Error queueing webhook delivery: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Wake the delivery worker after committing queued deliveries
+pub fn notify_new_deliveries() {
+    NEW_DELIVERIES.notify_one();
+}
+
+/// Value of the signature header for the body
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", mac.finalize().into_bytes().to_hex::<String>())
+}
+
+fn retry_delay(attempts: i64) -> chrono::Duration {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    chrono::Duration::seconds(std::cmp::min(
+        RETRY_BASE_DELAY_SECS * 2_i64.pow(exponent),
+        RETRY_MAX_DELAY_SECS,
+    ))
+}
+
+async fn send_delivery(
+    client: &awc::Client,
+    webhook: &WebhookDbObj,
+    delivery: &WebhookDeliveryDbObj,
+) -> Result<(), String> {
+    // IP addresses in the url are not resolved, check them again
+    webhook_host(&webhook.url).map_err(|e| e.to_string())?;
+    let response = client
+        .post(&webhook.url)
+        .timeout(REQUEST_TIMEOUT)
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("X-Webhook-Event", delivery.event.as_str()))
+        .insert_header(("X-Webhook-Delivery", delivery.id.as_str()))
+        .insert_header((
+            SIGNATURE_HEADER,
+            sign_payload(&webhook.secret, delivery.payload.as_bytes()),
+        ))
+        .send_body(delivery.payload.clone())
+        .await
+        .map_err(|e| format!("Error sending request: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!("Webhook responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Send the delivery, when it fails it is scheduled for retry with backoff
+async fn process_delivery(
+    conn: &SqlitePool,
+    client: &awc::Client,
+    mut delivery: WebhookDeliveryDbObj,
+) -> Result<(), WebPortalError> {
+    let Some(webhook) = get_webhook(conn, &delivery.webhook_id)
+        .await
+        .map_err(|e| err_custom_create!("Error getting webhook: {}", e))?
+    else {
+        return Ok(());
+    };
+    delivery.attempts += 1;
+    match send_delivery(client, &webhook, &delivery).await {
+        Ok(()) => {
+            log::debug!("Delivered {} to {}", delivery.event, webhook.url);
+            delivery.status = "delivered".to_string();
+            delivery.last_error = None;
+        }
+        Err(e) => {
+            log::warn!(
+                "Webhook delivery {} to {} failed (attempt {}): {}",
+                delivery.id,
+                webhook.url,
+                delivery.attempts,
+                e
+            );
+            if delivery.attempts >= MAX_ATTEMPTS {
+                delivery.status = "failed".to_string();
+            }
+            delivery.last_error = Some(e);
+            delivery.next_attempt = chrono::Utc::now() + retry_delay(delivery.attempts);
+        }
+    }
+    metrics::inc_webhook_delivery(&delivery.status);
+    update_webhook_delivery(conn, &delivery)
+        .await
+        .map_err(|e| err_custom_create!("Error updating webhook delivery: {}", e))
+}
+
+/// Send all due deliveries, up to DELIVERY_CONCURRENCY at once
+async fn send_due_deliveries(
+    conn: &SqlitePool,
+    client: &awc::Client,
+) -> Result<(), WebPortalError> {
+    let due = get_due_webhook_deliveries(conn, chrono::Utc::now(), DELIVERY_BATCH)
+        .await
+        .map_err(|e| err_custom_create!("Error getting webhook deliveries: {}", e))?;
+    futures_util::stream::iter(due)
+        .for_each_concurrent(DELIVERY_CONCURRENCY, |delivery| async move {
+            let id = delivery.id.clone();
+            if let Err(e) = process_delivery(conn, client, delivery).await {
+                log::error!("Error processing webhook delivery {}: {}", id, e);
+            }
+        })
+        .await;
+    Ok(())
+}
+
+/// Send queued deliveries as they come and retry failed ones, runs forever
+pub async fn run_delivery_worker(conn: SqlitePool) {
+    let client = webhook_client(WEBHOOK_ALLOWED_HOSTS.clone());
+    loop {
+        if let Err(e) = send_due_deliveries(&conn, &client).await {
+            log::error!("Error sending webhooks: {}", e);
+        }
+        // woken early when new deliveries are committed
+        let _ = tokio::time::timeout(POLL_INTERVAL, NEW_DELIVERIES.notified()).await;
+    }
+}
+
+#[test]
+fn webhook_events_test() {
+    let input = |events: Vec<WebhookEvent>| WebhookInput {
+        url: "https://example.com/hook".to_string(),
+        events,
+        min_transfer: Some("1000".to_string()),
+        min_reward: None,
+        secret: Some("secret".to_string()),
+    };
+    let webhook = webhook_from_input(
+        "0x01",
+        input(vec![
+            WebhookEvent::Withdrawal,
+            WebhookEvent::LargeTransfer,
+            WebhookEvent::ReconciliationFailed,
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        webhook.events,
+        "withdrawal,large_transfer,reconciliation_failed"
+    );
+    assert!(webhook_from_input("0x01", input(vec![])).is_err());
+    let webhook = WebhookDbObj {
+        created: "2024-10-23T11:00:00Z".parse().unwrap(),
+        ..webhook
+    };
+
+    let inspected =
+        |consensus_reward: &str, amount_incoming: &str, reconciled: bool| InspectedBlock {
+            block: BlockDbObj {
+                consensus_reward: consensus_reward.to_string(),
+                mev_reward: "5".to_string(),
+                amount_incoming: amount_incoming.to_string(),
+                unexplained_value: (!reconciled).then(|| "-42".to_string()),
+                reconciled,
+                ..BlockDbObj::test_block("0x01", 10, "2024-10-23T12:00:00Z")
+            },
+            txs: Vec::new(),
+            traces: Vec::new(),
+            counterparties: Vec::new(),
+        };
+    assert_eq!(
+        block_events(&webhook, &inspected("7", "999", true)),
+        vec![(WebhookEvent::Withdrawal, Some("7".to_string()))]
+    );
+    assert_eq!(
+        block_events(&webhook, &inspected("0", "1000", false)),
+        vec![
+            (WebhookEvent::LargeTransfer, Some("1000".to_string())),
+            (WebhookEvent::ReconciliationFailed, Some("-42".to_string()))
+        ]
+    );
+    // blocks from before the subscription are not announced
+    let mut old_block = inspected("7", "999", true);
+    old_block.block.timestamp = webhook.created;
+    assert!(block_events(&webhook, &old_block).is_empty());
+
+    // RFC 4231 test case 2
+    assert_eq!(
+        sign_payload("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn webhook_host_test() {
+    assert_eq!(
+        webhook_host("https://example.com/hook").unwrap(),
+        ("example.com".to_string(), 443)
+    );
+    assert_eq!(
+        webhook_host("http://93.184.216.34:8080/hook").unwrap(),
+        ("93.184.216.34".to_string(), 8080)
+    );
+    for url in [
+        "ftp://example.com/hook",
+        "http://localhost:8080/hook",
+        "http://127.0.0.1/hook",
+        "http://10.0.0.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hook",
+        "http://[fd00::1]/hook",
+        "http://[::ffff:192.168.1.1]/hook",
+    ] {
+        assert!(webhook_host(url).is_err(), "{url}");
+    }
+}
+
+#[tokio::test]
+async fn queue_block_events_test() {
+    use crate::db::model::transaction::ScanDbObj;
+    use crate::db::ops::transaction::insert_scan;
+    use crate::db::ops::webhook::insert_webhook;
+
+    let conn = crate::create_sqlite_connection(None, None, false, true)
+        .await
+        .unwrap();
+    let timestamp = "2024-10-23T00:00:00Z".parse().unwrap();
+    insert_scan(
+        &conn,
+        &ScanDbObj {
+            address: "0x01".to_string(),
+            first_block_number: 0,
+            first_block_timestamp: timestamp,
+            next_block_number: 11,
+            next_block_timestamp: timestamp,
+            finality_policy: None,
+            finality_block_number: None,
+            follow: false,
+        },
+    )
+    .await
+    .unwrap();
+    let webhook = WebhookDbObj {
+        id: "hook".to_string(),
+        address: "0x01".to_string(),
+        url: "https://example.com/hook".to_string(),
+        secret: "secret".to_string(),
+        events: "withdrawal".to_string(),
+        min_transfer: "0".to_string(),
+        min_reward: "0".to_string(),
+        created: "2024-10-23T11:00:00Z".parse().unwrap(),
+    };
+    insert_webhook(&conn, &webhook).await.unwrap();
+    let inspected = InspectedBlock {
+        block: BlockDbObj {
+            consensus_reward: "7".to_string(),
+            ..BlockDbObj::test_block("0x01", 10, "2024-10-23T12:00:00Z")
+        },
+        txs: Vec::new(),
+        traces: Vec::new(),
+        counterparties: Vec::new(),
+    };
+    // the same block saved again by a rescan
+    let mut db_conn = conn.acquire().await.unwrap();
+    queue_block_events(&mut db_conn, &inspected).await.unwrap();
+    queue_block_events(&mut db_conn, &inspected).await.unwrap();
+    drop(db_conn);
+
+    let due = get_due_webhook_deliveries(&conn, chrono::Utc::now(), DELIVERY_BATCH)
+        .await
+        .unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].id, "hook:10:withdrawal");
+}
+
+#[actix_web::test]
+async fn webhook_redirect_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let location = format!("http://{}/internal", target.local_addr().unwrap());
+    let url = format!("http://{}/hook", server.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = server.accept().await.unwrap();
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+        let response = format!(
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: {location}\r\nContent-Length: 0\r\n\r\n"
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let response = webhook_client(Vec::new())
+        .post(&url)
+        .send_body("{}")
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 307);
+    let followed = tokio::time::timeout(Duration::from_millis(200), target.accept()).await;
+    assert!(followed.is_err(), "redirect was followed");
+}
+
+#[actix_web::test]
+async fn public_resolver_test() {
+    let resolver = PublicResolver {
+        allowed_hosts: vec!["localhost".to_string()],
+    };
+    assert!(resolver.lookup("localhost", 80).await.is_ok());
+    let resolver = PublicResolver {
+        allowed_hosts: Vec::new(),
+    };
+    assert!(resolver.lookup("localhost", 80).await.is_err());
+}